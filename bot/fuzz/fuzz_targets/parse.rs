@@ -0,0 +1,11 @@
+#![no_main]
+
+use langbot::parse::ParsedRequest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    if let Some(req) = ParsedRequest::parse(text) {
+        assert!(!req.language.is_empty());
+        assert!(text.contains(&req.code));
+    }
+});
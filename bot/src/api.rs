@@ -0,0 +1,517 @@
+//! The HTTP API for running code without going through Discord, at `/v1`.
+//! Executions go through the same executor, and so the same pods, as the Discord handler.
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::sync::Mutex;
+use std::time;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info_span, warn, Instrument};
+
+use crate::discord::describe_exit;
+use crate::executor::RESTARTING_MSG;
+use crate::http::AppState;
+use crate::languages::Registry;
+use crate::podmanager::{Input, EXEC_TIMEOUT};
+use crate::store::StoredResult;
+
+/// Quotas are counted over this window.
+const QUOTA_WINDOW: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// The most file content we return for one execution, like the attachment limit on Discord.
+const MAX_FILES_SIZE: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyConfig {
+    key: String,
+    runs_per_hour: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaygroundConfig {
+    runs_per_hour: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    #[serde(default)]
+    keys: HashMap<String, KeyConfig>,
+    playground: Option<PlaygroundConfig>,
+}
+
+/// Someone with an API key.
+struct Client {
+    name: String,
+    runs_per_hour: Option<usize>,
+    /// When the runs within the quota window started, oldest first
+    runs: Mutex<VecDeque<time::Instant>>,
+}
+
+impl Client {
+    /// Count a run against the quota, or say how long until there's room for one.
+    fn charge(&self, now: time::Instant) -> Result<(), time::Duration> {
+        let limit = match self.runs_per_hour {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let mut runs = self.runs.lock().unwrap();
+        while runs.front().is_some_and(|start| now.duration_since(*start) >= QUOTA_WINDOW) {
+            runs.pop_front();
+        }
+
+        if runs.len() >= limit {
+            return match runs.front() {
+                Some(start) => Err(QUOTA_WINDOW - now.duration_since(*start)),
+                None => Err(QUOTA_WINDOW),
+            };
+        }
+
+        runs.push_back(now);
+        Ok(())
+    }
+}
+
+/// The API keys, by key.
+pub struct ApiKeys {
+    clients: HashMap<String, Client>,
+    /// Requests without a key, if the playground is enabled
+    playground: Option<Client>,
+}
+
+impl ApiKeys {
+    /// Parse a key file like:
+    ///
+    /// ```toml
+    /// [keys.grader]
+    /// key = "some long random string"
+    /// runs_per_hour = 600
+    /// ```
+    ///
+    /// Keys without `runs_per_hour` can run as much as they like.
+    /// A `[playground]` table enables the playground, and lets requests without a key
+    /// share its `runs_per_hour`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: KeysFile = match toml::from_str(text) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Invalid API key file: {}", err)),
+        };
+
+        let mut clients = HashMap::new();
+        for (name, config) in file.keys {
+            if config.key.len() < 16 {
+                return Err(format!(
+                    "Invalid API key file: the key for {} is shorter than 16 characters",
+                    name
+                ));
+            }
+
+            let client = Client {
+                name: name.clone(),
+                runs_per_hour: config.runs_per_hour,
+                runs: Mutex::new(VecDeque::new()),
+            };
+            if let Some(other) = clients.insert(config.key, client) {
+                return Err(format!(
+                    "Invalid API key file: {} and {} have the same key",
+                    other.name, name
+                ));
+            }
+        }
+
+        let playground = file.playground.map(|config| Client {
+            name: "playground".to_string(),
+            runs_per_hour: config.runs_per_hour,
+            runs: Mutex::new(VecDeque::new()),
+        });
+
+        Ok(Self {
+            clients,
+            playground,
+        })
+    }
+
+    /// Read the keys from the file named by `LANGBOT_API_KEYS`, if it's set.
+    pub fn from_env() -> Option<Self> {
+        let path = match env::var("LANGBOT_API_KEYS") {
+            Ok(path) if !path.is_empty() => path,
+            _ => return None,
+        };
+
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Reading LANGBOT_API_KEYS file {} failed: {}", path, err));
+        Some(Self::parse(&text).unwrap_or_else(|err| panic!("{}: {}", path, err)))
+    }
+
+    pub fn has_playground(&self) -> bool {
+        self.playground.is_some()
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Option<&Client> {
+        let value = match headers.get(header::AUTHORIZATION) {
+            Some(value) => value,
+            None => return self.playground.as_ref(),
+        };
+
+        let key = value.to_str().ok()?.strip_prefix("Bearer ")?;
+        self.clients.get(key)
+    }
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    retry_after: Option<time::Duration>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({"error": self.message}));
+        match self.retry_after {
+            Some(after) => {
+                let secs = (after.as_secs() + 1).to_string();
+                (self.status, [(header::RETRY_AFTER, secs)], body).into_response()
+            }
+            None => (self.status, body).into_response(),
+        }
+    }
+}
+
+fn authenticate<'a>(state: &'a AppState, headers: &HeaderMap) -> Result<&'a Client, ApiError> {
+    state
+        .api_keys
+        .as_ref()
+        .and_then(|keys| keys.authenticate(headers))
+        .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized"))
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunLimits {
+    /// Can only make the timeout shorter than EXEC_TIMEOUT
+    timeout_ms: Option<u64>,
+    /// Cut stdout and stderr down to this many bytes each
+    max_output_bytes: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    language: String,
+    code: String,
+    /// The program's stdin
+    #[serde(default)]
+    stdin: String,
+    /// The program's arguments
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    limits: RunLimits,
+}
+
+impl RunRequest {
+    /// The program's input, if the request is one we can run.
+    fn input(&self) -> Result<Input, String> {
+        if self.args.iter().any(|arg| arg.contains('\0')) {
+            return Err("Arguments can't contain NUL characters".into());
+        }
+        Ok(Input {
+            stdin: self.stdin.clone(),
+            args: self.args.clone(),
+        })
+    }
+
+    /// The timeout for the program, if the request is one we can run.
+    fn timeout(&self) -> Result<time::Duration, String> {
+        match self.limits.timeout_ms {
+            Some(ms) if ms == 0 || ms > EXEC_TIMEOUT.as_millis() as u64 => Err(format!(
+                "timeout_ms must be between 1 and {}",
+                EXEC_TIMEOUT.as_millis()
+            )),
+            Some(ms) => Ok(time::Duration::from_millis(ms)),
+            None => Ok(EXEC_TIMEOUT),
+        }
+    }
+}
+
+/// Cut `text` down to at most `max` bytes, on a character boundary.
+/// Returns whether anything was cut.
+fn cut(text: &mut String, max: Option<usize>) -> bool {
+    let max = match max {
+        Some(max) if max < text.len() => max,
+        _ => return false,
+    };
+
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    true
+}
+
+/// Every language, and whether it works right now.
+async fn languages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    authenticate(&state, &headers)?;
+
+    let registry = state.podman.languages();
+    let languages: Vec<serde_json::Value> = Registry::names()
+        .into_iter()
+        .map(|name| match registry.why_disabled(&name) {
+            Some(reason) => json!({"name": name, "enabled": false, "reason": reason}),
+            None => json!({"name": name, "enabled": true}),
+        })
+        .collect();
+
+    Ok(Json(json!({ "languages": languages })))
+}
+
+/// Run some code, and return its output and the files it created.
+async fn run(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Result<Json<RunRequest>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let client = authenticate(&state, &headers)?;
+    // Malformed bodies, and ones with fields we don't know, are bad requests too
+    let Json(req) = body.map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.body_text()))?;
+    let timeout = req
+        .timeout()
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+    let input = req
+        .input()
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+
+    let language = req.language.to_lowercase();
+    if !Registry::names().contains(&language) {
+        let err = format!("Unknown language: {}", language);
+        return Err(ApiError::new(StatusCode::NOT_FOUND, err));
+    }
+    if let Some(reason) = state.podman.languages().why_disabled(&language) {
+        let err = format!("{} is disabled, it failed its self-test: {}", language, reason);
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, err));
+    }
+
+    if let Err(after) = client.charge(time::Instant::now()) {
+        return Err(ApiError {
+            retry_after: Some(after),
+            ..ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Quota exceeded")
+        });
+    }
+
+    let _job = state.executor.job();
+    let span = info_span!(
+        "api_request",
+        client = %client.name,
+        language = %language,
+        container = tracing::field::Empty,
+    );
+    let start = time::Instant::now();
+    let res = state
+        .executor
+        .run_with_timeout(language.clone(), req.code.clone(), input, timeout)
+        .instrument(span)
+        .await;
+    let elapsed = start.elapsed();
+
+    let output = match res {
+        Ok(output) => output,
+        Err(err) if err == RESTARTING_MSG => {
+            return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, err));
+        }
+        Err(err) => {
+            warn!(client = %client.name, error = %err, "Execution failed");
+            return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err));
+        }
+    };
+
+    let mut stdout = output.stdout.clone().unwrap_or_default();
+    let mut stderr = output.stderr.clone().unwrap_or_default();
+    let stdout_cut = cut(&mut stdout, req.limits.max_output_bytes);
+    let stderr_cut = cut(&mut stderr, req.limits.max_output_bytes);
+
+    let mut files = Vec::new();
+    let mut files_cut = false;
+    let mut total_size = 0;
+    let output_files = output
+        .read_files()
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+    let result_url = state.results.as_ref().and_then(|store| {
+        let result = StoredResult::new(&language, &req.code, &output, &output_files);
+        match store.save(&result) {
+            Ok(id) => Some(store.link(&id)),
+            Err(err) => {
+                warn!(error = %err, "Storing result failed");
+                None
+            }
+        }
+    });
+
+    for file in &output_files {
+        total_size += file.content.len();
+        if total_size > MAX_FILES_SIZE {
+            files_cut = true;
+            break;
+        }
+
+        files.push(json!({
+            "name": file.name,
+            "size": file.content.len(),
+            "content_base64": BASE64.encode(&file.content),
+        }));
+    }
+
+    Ok(Json(json!({
+        "language": language,
+        "stdout": stdout,
+        "stderr": stderr,
+        "success": output.status.success(),
+        "exit_code": output.status.code(),
+        "signal": output.status.signal(),
+        "exit": describe_exit(&output.status),
+        "cached": output.cached,
+        "time_ms": elapsed.as_millis() as u64,
+        "truncated": {
+            "stdout": stdout_cut,
+            "stderr": stderr_cut,
+            "files": files_cut,
+        },
+        "files": files,
+        "result_url": result_url,
+    })))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/run", post(run))
+        .route("/v1/languages", get(languages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: &str = r#"
+        [keys.grader]
+        key = "0123456789abcdef"
+        runs_per_hour = 2
+
+        [keys.ci]
+        key = "fedcba9876543210"
+    "#;
+
+    fn bearer(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", key).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn authenticates_and_enforces_quotas() {
+        let keys = ApiKeys::parse(KEYS).unwrap();
+        assert!(keys.authenticate(&bearer("nope")).is_none());
+
+        let grader = keys.authenticate(&bearer("0123456789abcdef")).unwrap();
+        assert_eq!(grader.name, "grader");
+        let start = time::Instant::now();
+        let minute = time::Duration::from_secs(60);
+        assert_eq!(grader.charge(start), Ok(()));
+        assert_eq!(grader.charge(start + minute), Ok(()));
+        assert_eq!(grader.charge(start + 2 * minute), Err(QUOTA_WINDOW - 2 * minute));
+        assert_eq!(grader.charge(start + QUOTA_WINDOW), Ok(()));
+
+        let ci = keys.authenticate(&bearer("fedcba9876543210")).unwrap();
+        for _ in 0..100 {
+            assert_eq!(ci.charge(start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn lets_playground_in_without_key() {
+        let keys = ApiKeys::parse(KEYS).unwrap();
+        assert!(!keys.has_playground());
+        assert!(keys.authenticate(&HeaderMap::new()).is_none());
+
+        let keys = ApiKeys::parse(&format!("{}\n[playground]\nruns_per_hour = 1\n", KEYS)).unwrap();
+        assert!(keys.has_playground());
+        let playground = keys.authenticate(&HeaderMap::new()).unwrap();
+        assert_eq!(playground.name, "playground");
+        assert!(playground.charge(time::Instant::now()).is_ok());
+        assert!(playground.charge(time::Instant::now()).is_err());
+
+        // A wrong key is still wrong
+        assert!(keys.authenticate(&bearer("nope")).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_key_files() {
+        let twice = "[keys.a]\nkey = \"0123456789abcdef\"\n[keys.b]\nkey = \"0123456789abcdef\"\n";
+        let err = ApiKeys::parse(twice);
+        assert!(err.err().unwrap().contains("have the same key"));
+        let err = ApiKeys::parse("[keys.a]\nkey = \"short\"\n");
+        assert!(err.err().unwrap().contains("shorter than 16"));
+        assert!(ApiKeys::parse("[keys.a]\nkey = \"0123456789abcdef\"\nquota = 1\n").is_err());
+        assert!(ApiKeys::parse("").unwrap().clients.is_empty());
+    }
+
+    #[test]
+    fn validates_run_requests() {
+        let req = |body: serde_json::Value| serde_json::from_value::<RunRequest>(body).unwrap();
+        assert_eq!(req(json!({"language": "c", "code": ""})).timeout(), Ok(EXEC_TIMEOUT));
+
+        let short = req(json!({"language": "c", "code": "", "limits": {"timeout_ms": 500}}));
+        assert_eq!(short.timeout(), Ok(time::Duration::from_millis(500)));
+        let long = req(json!({"language": "c", "code": "", "limits": {"timeout_ms": 60000}}));
+        assert!(long.timeout().is_err());
+
+        let input = req(json!({"language": "c", "code": "", "stdin": "1 2", "args": ["-v"]}));
+        let expected = Input {
+            stdin: "1 2".to_string(),
+            args: vec!["-v".to_string()],
+        };
+        assert_eq!(input.input(), Ok(expected));
+        assert_eq!(req(json!({"language": "c", "code": ""})).input(), Ok(Input::default()));
+        let nul = req(json!({"language": "c", "code": "", "args": ["a\u{0}b"]}));
+        assert!(nul.input().is_err());
+        let memory = json!({"language": "c", "code": "", "limits": {"memory": 1}});
+        assert!(serde_json::from_value::<RunRequest>(memory).is_err());
+    }
+
+    #[test]
+    fn cuts_output_on_char_boundaries() {
+        let mut text = "aøb".to_string();
+        assert!(!cut(&mut text, None));
+        assert!(!cut(&mut text, Some(4)));
+        assert!(cut(&mut text, Some(2)));
+        assert_eq!(text, "a");
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use super::{restored_name, Backend, ExecOutput, Limits};
+
+/// Runs everything by spawning `podman` processes.
+pub struct CliBackend {
+    limits: Limits,
+}
+
+impl CliBackend {
+    pub fn new(limits: Limits) -> Self {
+        Self { limits }
+    }
+
+    fn output(&self, what: &str, cmd: &mut Command) -> Result<Output, String> {
+        let output = match cmd.output() {
+            Ok(output) => output,
+            Err(err) => return Err(format!("{} failed: {}", what, err)),
+        };
+
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                what,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+impl Backend for CliBackend {
+    fn name(&self) -> &'static str {
+        "cli"
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.output(
+            "podman version",
+            Command::new("podman").arg("version").stdout(Stdio::null()),
+        )?;
+        Ok(())
+    }
+
+    fn create(&self, tag: &str, labels: &[(&str, &str)]) -> Result<String, String> {
+        let mut cmd = Command::new("podman");
+        cmd.arg("container")
+            .arg("create")
+            .arg("--rm")
+            .arg("--network=none");
+        if let Some(memory) = self.limits.memory {
+            cmd.arg(format!("--memory={}", memory))
+                .arg(format!("--memory-swap={}", memory));
+        }
+        if let Some(pids) = self.limits.pids {
+            cmd.arg(format!("--pids-limit={}", pids));
+        }
+        if let Some(cpus) = self.limits.cpus {
+            cmd.arg(format!("--cpus={}", cpus));
+        }
+        for (key, val) in labels {
+            cmd.arg(format!("--label={}={}", key, val));
+        }
+        cmd.arg(tag).arg("tail").arg("-f").arg("/dev/null");
+        let output = self.output("Creating container", &mut cmd)?;
+
+        let id = match String::from_utf8(output.stdout) {
+            Ok(id) => id.trim().to_string(),
+            Err(err) => return Err(format!("Podman retuned invalid UTF-8: {}", err)),
+        };
+
+        let started = self.output(
+            "Starting container",
+            Command::new("podman").arg("container").arg("start").arg(&id),
+        );
+        if let Err(err) = started {
+            let _ = self.remove(&[id]);
+            return Err(err);
+        }
+
+        Ok(id)
+    }
+
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        let child = Command::new("podman")
+            .arg("exec")
+            .arg("-i")
+            .arg(id)
+            .arg("./scripts/run.sh")
+            .arg(language)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return Err(format!("Running program failed: {}", err)),
+        };
+
+        if let Some(mut child_stdin) = child.stdin.take() {
+            match child_stdin.write_all(stdin) {
+                Ok(()) => (),
+                Err(err) => return Err(format!("Running program failed: {}", err)),
+            }
+        }
+
+        let output = match child.wait_with_output() {
+            Ok(output) => output,
+            Err(err) => return Err(format!("Running program failed: {}", err)),
+        };
+
+        Ok(ExecOutput {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn files(&self, id: &str, language: &str) -> Result<Vec<u8>, String> {
+        let output = self.output(
+            "Getting files",
+            Command::new("podman")
+                .arg("exec")
+                .arg(id)
+                .arg("./scripts/get-files.sh")
+                .arg(language)
+                .stdin(Stdio::null()),
+        )?;
+        Ok(output.stdout)
+    }
+
+    fn prime(&self, id: &str, language: &str) -> Result<(), String> {
+        self.output(
+            "Priming container",
+            Command::new("podman")
+                .arg("exec")
+                .arg(id)
+                .arg("./scripts/prime.sh")
+                .arg(language)
+                .stdin(Stdio::null()),
+        )?;
+        Ok(())
+    }
+
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        let output = self.output(
+            "Self-test",
+            Command::new("podman")
+                .arg("exec")
+                .arg(id)
+                .arg("./scripts/self-test.sh")
+                .args(languages)
+                .stdin(Stdio::null()),
+        )?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn snapshot(&self, id: &str) -> Result<(), String> {
+        self.output(
+            "Snapshotting container",
+            Command::new("podman")
+                .arg("exec")
+                .arg("--user=root")
+                .arg(id)
+                .arg("langbot-reset")
+                .arg("snapshot")
+                .stdin(Stdio::null()),
+        )?;
+        Ok(())
+    }
+
+    fn reset(&self, id: &str) -> Result<(), String> {
+        self.output(
+            "Resetting container",
+            Command::new("podman")
+                .arg("exec")
+                .arg("--user=root")
+                .arg(id)
+                .arg("langbot-reset")
+                .stdin(Stdio::null()),
+        )?;
+        Ok(())
+    }
+
+    fn checkpoint(&self, id: &str, language: &str, path: &Path) -> Result<(), String> {
+        let res = self
+            .output(
+                "Prewarming container",
+                Command::new("podman")
+                    .arg("exec")
+                    .arg(id)
+                    .arg("./scripts/prewarm.sh")
+                    .arg(language)
+                    .stdin(Stdio::null()),
+            )
+            .and_then(|_| {
+                self.output(
+                    "Checkpointing container",
+                    Command::new("podman")
+                        .arg("container")
+                        .arg("checkpoint")
+                        .arg(format!("--export={}", path.display()))
+                        .arg(id),
+                )
+            });
+        let _ = self.remove(&[id.to_string()]);
+        res?;
+        Ok(())
+    }
+
+    fn restore(&self, path: &Path) -> Result<String, String> {
+        let output = self.output(
+            "Restoring container",
+            Command::new("podman")
+                .arg("container")
+                .arg("restore")
+                .arg(format!("--import={}", path.display()))
+                .arg(format!("--name={}", restored_name())),
+        )?;
+
+        match String::from_utf8(output.stdout) {
+            Ok(id) => Ok(id.trim().to_string()),
+            Err(err) => Err(format!("Podman retuned invalid UTF-8: {}", err)),
+        }
+    }
+
+    fn kill(&self, id: &str) -> Result<(), String> {
+        self.output(
+            "Killing container",
+            Command::new("podman").arg("container").arg("kill").arg(id),
+        )?;
+        Ok(())
+    }
+
+    fn image_id(&self, tag: &str) -> Result<String, String> {
+        let output = self.output(
+            "Inspecting image",
+            Command::new("podman")
+                .arg("image")
+                .arg("inspect")
+                .arg("--format={{.Id}}")
+                .arg(tag),
+        )?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn list(&self, key: &str) -> Result<Vec<String>, String> {
+        let output = self.output(
+            "Listing containers",
+            Command::new("podman")
+                .arg("container")
+                .arg("ls")
+                .arg("--all")
+                .arg("--no-trunc")
+                .arg(format!("--filter=label={}", key))
+                .arg("--format={{.ID}}"),
+        )?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect())
+    }
+
+    fn remove(&self, ids: &[String]) -> Result<(), String> {
+        self.output(
+            "Removing containers",
+            Command::new("podman")
+                .arg("container")
+                .arg("rm")
+                .arg("--force")
+                .args(ids),
+        )?;
+        Ok(())
+    }
+}
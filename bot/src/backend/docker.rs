@@ -0,0 +1,180 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use super::engine::EngineApi;
+use super::{Backend, ExecOutput, Limits};
+
+/// Talks to the Docker Engine API over `/var/run/docker.sock` (or wherever it lives),
+/// for hosts which don't have podman.
+pub struct DockerBackend {
+    api: EngineApi,
+    limits: Limits,
+}
+
+impl DockerBackend {
+    pub fn new(socket: impl Into<PathBuf>, limits: Limits) -> Self {
+        Self {
+            api: EngineApi::new(socket, "/v1.41"),
+            limits,
+        }
+    }
+}
+
+impl Backend for DockerBackend {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.api.ping()
+    }
+
+    fn create(&self, tag: &str, labels: &[(&str, &str)]) -> Result<String, String> {
+        let labels: serde_json::Map<String, serde_json::Value> = labels
+            .iter()
+            .map(|(key, val)| (key.to_string(), json!(val)))
+            .collect();
+
+        let mut host_config = json!({
+            "AutoRemove": true,
+            "NetworkMode": "none",
+        });
+        if let Some(memory) = self.limits.memory {
+            host_config["Memory"] = json!(memory);
+            // No swap on top of the memory limit
+            host_config["MemorySwap"] = json!(memory);
+        }
+        if let Some(pids) = self.limits.pids {
+            host_config["PidsLimit"] = json!(pids);
+        }
+        if let Some(cpus) = self.limits.cpus {
+            host_config["NanoCpus"] = json!((cpus * 1e9) as i64);
+        }
+
+        let body = json!({
+            "Image": tag,
+            "Cmd": ["tail", "-f", "/dev/null"],
+            "Labels": labels,
+            "NetworkDisabled": true,
+            "HostConfig": host_config,
+        });
+        self.api.create(&body)
+    }
+
+    fn image_id(&self, tag: &str) -> Result<String, String> {
+        self.api.image_id(tag)
+    }
+
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        self.api.run(id, language, args, stdin)
+    }
+
+    fn files(&self, id: &str, language: &str) -> Result<Vec<u8>, String> {
+        self.api.files(id, language)
+    }
+
+    fn prime(&self, id: &str, language: &str) -> Result<(), String> {
+        self.api.prime(id, language)
+    }
+
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        self.api.self_test(id, languages)
+    }
+
+    fn snapshot(&self, id: &str) -> Result<(), String> {
+        self.api.reset(id, "Snapshotting container", &["snapshot"])
+    }
+
+    fn reset(&self, id: &str) -> Result<(), String> {
+        self.api.reset(id, "Resetting container", &[])
+    }
+
+    fn kill(&self, id: &str) -> Result<(), String> {
+        self.api.kill(id)
+    }
+
+    fn list(&self, key: &str) -> Result<Vec<String>, String> {
+        self.api.list(key)
+    }
+
+    fn remove(&self, ids: &[String]) -> Result<(), String> {
+        self.api.remove(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::engine::testutil::{fake_engine, frame, Reply};
+
+    #[test]
+    fn creates_container_with_limits() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v1.41/containers/create") => Reply::Json(201, json!({"Id": "d0"})),
+            ("POST", "/v1.41/containers/d0/start") => Reply::Status(204),
+            _ => Reply::Status(500),
+        });
+
+        let limits = Limits {
+            memory: Some(256 * 1024 * 1024),
+            pids: Some(64),
+            cpus: Some(0.5),
+        };
+        let backend = DockerBackend::new(&engine.socket, limits);
+        assert_eq!(backend.create("langbot", &[("langbot.instance", "x")]).unwrap(), "d0");
+
+        let requests = engine.requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["Image"], "langbot");
+        assert_eq!(body["Labels"]["langbot.instance"], "x");
+        assert_eq!(body["HostConfig"]["NetworkMode"], "none");
+        assert_eq!(body["HostConfig"]["AutoRemove"], true);
+        assert_eq!(body["HostConfig"]["Memory"], 256 * 1024 * 1024);
+        assert_eq!(body["HostConfig"]["PidsLimit"], 64);
+        assert_eq!(body["HostConfig"]["NanoCpus"], 500_000_000);
+    }
+
+    #[test]
+    fn runs_and_kills() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v1.41/containers/d0/exec") => Reply::Json(201, json!({"Id": "e"})),
+            ("POST", "/v1.41/exec/e/start") => {
+                Reply::Attach(Box::new(|stdin| frame(1, &stdin.to_ascii_uppercase())))
+            }
+            ("GET", "/v1.41/exec/e/json") => Reply::Json(200, json!({"ExitCode": 0})),
+            ("POST", "/v1.41/containers/d0/kill") => Reply::Status(204),
+            _ => Reply::Status(500),
+        });
+
+        let backend = DockerBackend::new(&engine.socket, Limits::default());
+        let output = backend.run("d0", "shell", &[], b"echo hi").unwrap();
+        assert_eq!(output.stdout, b"ECHO HI");
+        assert!(output.status.success());
+        backend.kill("d0").unwrap();
+    }
+
+    #[test]
+    fn killed_exec_keeps_output() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v1.41/containers/d0/exec") => Reply::Json(201, json!({"Id": "e"})),
+            ("POST", "/v1.41/exec/e/start") => Reply::Attach(Box::new(|_| frame(1, b"1\n1\n"))),
+            // AutoRemove took the container and its exec sessions with it
+            ("GET", "/v1.41/exec/e/json") => {
+                Reply::Json(404, json!({"message": "No such exec instance: e"}))
+            }
+            _ => Reply::Status(500),
+        });
+
+        let backend = DockerBackend::new(&engine.socket, Limits::default());
+        let output = backend.run("d0", "shell", &[], b"yes 1").unwrap();
+        assert_eq!(output.stdout, b"1\n1\n");
+        assert_eq!(output.status.code(), Some(137));
+    }
+}
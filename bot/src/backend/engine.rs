@@ -0,0 +1,441 @@
+//! Operations shared by engines speaking the Docker Engine API (or podman's flavor of it)
+//! over a unix socket.
+
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::thread;
+use std::time;
+
+use serde_json::json;
+
+use super::unixhttp::{demux, encode_query, Client};
+use super::{strip_archive_root, ExecOutput, APP_DIR};
+
+/// How often, and how many times, to check whether an exec session whose output has ended
+/// has finished, since the engine may not have noticed yet
+const EXIT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+const EXIT_POLLS: usize = 500;
+
+pub(super) struct EngineApi {
+    client: Client,
+    prefix: &'static str,
+}
+
+/// Build an ExitStatus which looks like a process exiting with `code`.
+fn exit_status(code: i64) -> ExitStatus {
+    ExitStatus::from_raw(((code as i32) & 0xff) << 8)
+}
+
+fn get_id(what: &str, body: &[u8]) -> Result<String, String> {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(val) => match val.get("Id").and_then(|id| id.as_str()) {
+            Some(id) => Ok(id.to_string()),
+            None => Err(format!("{} returned no ID", what)),
+        },
+        Err(err) => Err(format!("{} returned invalid JSON: {}", what, err)),
+    }
+}
+
+impl EngineApi {
+    /// `prefix` is prepended to every path, e.g `/v4.0.0/libpod`.
+    pub fn new(socket: impl Into<PathBuf>, prefix: &'static str) -> Self {
+        Self {
+            client: Client::new(socket),
+            prefix,
+        }
+    }
+
+    pub fn ping(&self) -> Result<(), String> {
+        self.client
+            .request("GET", &format!("{}/_ping", self.prefix), None)?
+            .error_for_status("Ping")?;
+        Ok(())
+    }
+
+    /// Create a container from an engine specific body, and start it.
+    pub fn create(&self, body: &serde_json::Value) -> Result<String, String> {
+        let resp = self
+            .client
+            .request("POST", &format!("{}/containers/create", self.prefix), Some(body))?
+            .error_for_status("Creating container")?;
+        let id = get_id("Creating container", &resp.body)?;
+
+        let started = self
+            .client
+            .request("POST", &format!("{}/containers/{}/start", self.prefix, id), None)
+            .and_then(|resp| resp.error_for_status("Starting container"));
+        if let Err(err) = started {
+            let _ = self.remove(&[id]);
+            return Err(err);
+        }
+
+        Ok(id)
+    }
+
+    pub fn image_id(&self, tag: &str) -> Result<String, String> {
+        let resp = self
+            .client
+            .request("GET", &format!("{}/images/{}/json", self.prefix, tag), None)?
+            .error_for_status("Inspecting image")?;
+        get_id("Inspecting image", &resp.body)
+    }
+
+    /// Run `cmd` in the container, as `user` if given or as the image's user otherwise.
+    pub fn exec(
+        &self,
+        id: &str,
+        cmd: &[&str],
+        stdin: Option<&[u8]>,
+        user: Option<&str>,
+    ) -> Result<ExecOutput, String> {
+        let mut body = json!({
+            "AttachStdin": stdin.is_some(),
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Cmd": cmd,
+        });
+        if let Some(user) = user {
+            body["User"] = json!(user);
+        }
+        let resp = self
+            .client
+            .request("POST", &format!("{}/containers/{}/exec", self.prefix, id), Some(&body))?
+            .error_for_status("Creating exec session")?;
+        let exec_id = get_id("Creating exec session", &resp.body)?;
+
+        let body = json!({"Detach": false, "Tty": false});
+        let path = format!("{}/exec/{}/start", self.prefix, exec_id);
+        let (status, mut stream, mut raw) = self.client.upgrade("POST", &path, Some(&body))?;
+        if status != 101 && status != 200 {
+            return Err(format!("Starting exec session failed ({})", status));
+        }
+
+        if let Some(stdin) = stdin {
+            if let Err(err) = stream.write_all(stdin) {
+                return Err(format!("Writing stdin failed: {}", err));
+            }
+        }
+        // Closing our write half is what signals EOF on the process' stdin
+        let _ = stream.shutdown(Shutdown::Write);
+
+        if let Err(err) = stream.read_to_end(&mut raw) {
+            return Err(format!("Reading output failed: {}", err));
+        }
+        let (stdout, stderr) = demux(&raw)?;
+
+        Ok(ExecOutput {
+            status: exit_status(self.exit_code(&exec_id)?),
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Wait for an exec session to finish, and return its exit code.
+    fn exit_code(&self, exec_id: &str) -> Result<i64, String> {
+        let path = format!("{}/exec/{}/json", self.prefix, exec_id);
+        for _ in 0..EXIT_POLLS {
+            let resp = self.client.request("GET", &path, None)?;
+            // Containers are removed when they're killed, taking their exec sessions with them,
+            // so an exec session which doesn't exist any more was killed along with its container
+            if resp.status == 404 {
+                return Ok(128 + 9);
+            }
+            let resp = resp.error_for_status("Inspecting exec session")?;
+            let val = serde_json::from_slice::<serde_json::Value>(&resp.body)
+                .map_err(|err| format!("Inspecting exec session returned invalid JSON: {}", err))?;
+
+            if val.get("Running").and_then(|running| running.as_bool()) == Some(true) {
+                thread::sleep(EXIT_POLL_INTERVAL);
+                continue;
+            }
+            return match val.get("ExitCode").and_then(|code| code.as_i64()) {
+                Some(code) => Ok(code),
+                None => Err("Inspecting exec session returned no exit code".into()),
+            };
+        }
+
+        Err("Exec session didn't finish after its output ended".into())
+    }
+
+    pub fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        let mut cmd = vec!["./scripts/run.sh", language];
+        cmd.extend(args.iter().map(String::as_str));
+        self.exec(id, &cmd, Some(stdin), None)
+    }
+
+    /// Run `langbot-reset` with `args` as root, failing if it exits with a non-zero status.
+    pub fn reset(&self, id: &str, what: &str, args: &[&str]) -> Result<(), String> {
+        let mut cmd = vec!["langbot-reset"];
+        cmd.extend_from_slice(args);
+        self.exec_checked(id, what, &cmd, Some("root"))
+    }
+
+    pub fn prime(&self, id: &str, language: &str) -> Result<(), String> {
+        self.exec_checked(id, "Priming container", &["./scripts/prime.sh", language], None)
+    }
+
+    pub fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        let mut cmd = vec!["./scripts/self-test.sh"];
+        cmd.extend(languages.iter().map(String::as_str));
+        let output = self.exec(id, &cmd, None, None)?;
+        if !output.status.success() {
+            return Err(format!(
+                "Self-test failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Like `exec` without stdin, failing if the command exits with a non-zero status.
+    fn exec_checked(
+        &self,
+        id: &str,
+        what: &str,
+        cmd: &[&str],
+        user: Option<&str>,
+    ) -> Result<(), String> {
+        let output = self.exec(id, cmd, None, user)?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                what,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn files(&self, id: &str, language: &str) -> Result<Vec<u8>, String> {
+        let path = format!("{}/deploy/{}/wd", APP_DIR, language);
+        let resp = self.client.request(
+            "GET",
+            &format!("{}/containers/{}/archive?path={}", self.prefix, id, encode_query(&path)),
+            None,
+        )?;
+        if resp.status == 404 {
+            return Ok(Vec::new());
+        }
+
+        let resp = resp.error_for_status("Getting files")?;
+        strip_archive_root(&resp.body)
+    }
+
+    /// Checkpoint a container with CRIU and get the exported archive. This is podman specific.
+    pub fn checkpoint(&self, id: &str) -> Result<Vec<u8>, String> {
+        let resp = self
+            .client
+            .request(
+                "POST",
+                &format!("{}/containers/{}/checkpoint?export=true", self.prefix, id),
+                None,
+            )?
+            .error_for_status("Checkpointing container")?;
+        Ok(resp.body)
+    }
+
+    /// Import a checkpoint archive as a new container named `name`. This is podman specific.
+    pub fn restore(&self, archive: &[u8], name: &str) -> Result<String, String> {
+        let path = format!(
+            "{}/containers/import/restore?import=true&name={}",
+            self.prefix,
+            encode_query(name)
+        );
+        let resp = self
+            .client
+            .request_raw("POST", &path, Some(("application/x-tar", archive)))?
+            .error_for_status("Restoring container")?;
+        get_id("Restoring container", &resp.body)
+    }
+
+    pub fn kill(&self, id: &str) -> Result<(), String> {
+        self.client
+            .request("POST", &format!("{}/containers/{}/kill", self.prefix, id), None)?
+            .error_for_status("Killing container")?;
+        Ok(())
+    }
+
+    pub fn list(&self, key: &str) -> Result<Vec<String>, String> {
+        let filters = json!({"label": [key]}).to_string();
+        let path = format!(
+            "{}/containers/json?all=true&filters={}",
+            self.prefix,
+            encode_query(&filters)
+        );
+        let resp = self
+            .client
+            .request("GET", &path, None)?
+            .error_for_status("Listing containers")?;
+
+        let containers = match serde_json::from_slice::<Vec<serde_json::Value>>(&resp.body) {
+            Ok(containers) => containers,
+            Err(err) => return Err(format!("Listing containers returned invalid JSON: {}", err)),
+        };
+        Ok(containers
+            .iter()
+            .filter_map(|c| c.get("Id").and_then(|id| id.as_str()).map(String::from))
+            .collect())
+    }
+
+    pub fn remove(&self, ids: &[String]) -> Result<(), String> {
+        for id in ids {
+            let resp = self.client.request(
+                "DELETE",
+                &format!("{}/containers/{}?force=true", self.prefix, id),
+                None,
+            )?;
+            if resp.status != 404 {
+                resp.error_for_status("Removing container")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(super) mod testutil {
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// A request as seen by the stand-in server.
+    pub struct Request {
+        pub method: String,
+        pub path: String,
+        pub body: Vec<u8>,
+    }
+
+    pub type Respond = Box<dyn Fn(&[u8]) -> Vec<u8> + Send>;
+
+    pub enum Reply {
+        Json(u16, serde_json::Value),
+        Status(u16),
+        Raw(u16, Vec<u8>),
+        /// Hijack the connection: read stdin until EOF, then send this multiplexed stream.
+        Attach(Respond),
+    }
+
+    /// A stand-in for the engine's socket, answering requests with `handler`
+    /// and recording them.
+    pub struct FakeEngine {
+        pub socket: PathBuf,
+        pub requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl Drop for FakeEngine {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.socket);
+        }
+    }
+
+    fn read_request(stream: &mut UnixStream) -> Option<Request> {
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 1];
+        while !raw.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => raw.push(buf[0]),
+            }
+        }
+
+        let head = String::from_utf8_lossy(&raw).to_string();
+        let mut parts = head.split(' ');
+        let method = parts.next()?.to_string();
+        let path = parts.next()?.to_string();
+        let len = head
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).ok()?;
+        Some(Request { method, path, body })
+    }
+
+    pub fn frame(stream: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![stream, 0, 0, 0];
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    pub fn fake_engine<F>(handler: F) -> FakeEngine
+    where
+        F: Fn(&Request) -> Reply + Send + 'static,
+    {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let socket = std::env::temp_dir().join(format!(
+            "langbot-test-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let requests_th = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let req = match read_request(&mut stream) {
+                    Some(req) => req,
+                    None => continue,
+                };
+
+                let reply = handler(&req);
+                requests_th.lock().unwrap().push(req);
+                let (status, body) = match reply {
+                    Reply::Json(status, val) => (status, val.to_string().into_bytes()),
+                    Reply::Status(status) => (status, Vec::new()),
+                    Reply::Raw(status, body) => (status, body),
+                    Reply::Attach(respond) => {
+                        let _ = stream.write_all(
+                            b"HTTP/1.1 101 UPGRADED\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n",
+                        );
+                        let mut stdin = Vec::new();
+                        let _ = stream.read_to_end(&mut stdin);
+                        let _ = stream.write_all(&respond(&stdin));
+                        continue;
+                    }
+                };
+
+                let head = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+
+        FakeEngine { socket, requests }
+    }
+
+    pub fn tar_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+}
@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use super::engine::EngineApi;
+use super::{restored_name, Backend, ExecOutput, Limits};
+
+/// Talks to podman's REST API over its unix socket,
+/// so that an execution doesn't have to spawn any processes.
+pub struct LibpodBackend {
+    api: EngineApi,
+    limits: Limits,
+}
+
+impl LibpodBackend {
+    pub fn new(socket: impl Into<PathBuf>, limits: Limits) -> Self {
+        Self {
+            api: EngineApi::new(socket, "/v4.0.0/libpod"),
+            limits,
+        }
+    }
+}
+
+impl Backend for LibpodBackend {
+    fn name(&self) -> &'static str {
+        "libpod"
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.api.ping()
+    }
+
+    fn create(&self, tag: &str, labels: &[(&str, &str)]) -> Result<String, String> {
+        let labels: serde_json::Map<String, serde_json::Value> = labels
+            .iter()
+            .map(|(key, val)| (key.to_string(), json!(val)))
+            .collect();
+
+        // These are OCI runtime spec resource limits
+        let mut resources = json!({});
+        if let Some(memory) = self.limits.memory {
+            resources["memory"] = json!({"limit": memory, "swap": memory});
+        }
+        if let Some(pids) = self.limits.pids {
+            resources["pids"] = json!({"limit": pids});
+        }
+        if let Some(cpus) = self.limits.cpus {
+            resources["cpu"] = json!({"quota": (cpus * 100_000.0) as i64, "period": 100_000});
+        }
+
+        let body = json!({
+            "image": tag,
+            "command": ["tail", "-f", "/dev/null"],
+            "remove": true,
+            "netns": {"nsmode": "none"},
+            "labels": labels,
+            "resource_limits": resources,
+        });
+        self.api.create(&body)
+    }
+
+    fn image_id(&self, tag: &str) -> Result<String, String> {
+        self.api.image_id(tag)
+    }
+
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        self.api.run(id, language, args, stdin)
+    }
+
+    fn files(&self, id: &str, language: &str) -> Result<Vec<u8>, String> {
+        self.api.files(id, language)
+    }
+
+    fn prime(&self, id: &str, language: &str) -> Result<(), String> {
+        self.api.prime(id, language)
+    }
+
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        self.api.self_test(id, languages)
+    }
+
+    fn snapshot(&self, id: &str) -> Result<(), String> {
+        self.api.reset(id, "Snapshotting container", &["snapshot"])
+    }
+
+    fn reset(&self, id: &str) -> Result<(), String> {
+        self.api.reset(id, "Resetting container", &[])
+    }
+
+    fn checkpoint(&self, id: &str, language: &str, path: &Path) -> Result<(), String> {
+        let archive = self
+            .api
+            .exec(id, &["./scripts/prewarm.sh", language], None, None)
+            .and_then(|output| {
+                if !output.status.success() {
+                    return Err(format!(
+                        "Prewarming container failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim_end()
+                    ));
+                }
+                self.api.checkpoint(id)
+            });
+        let _ = self.api.remove(&[id.to_string()]);
+        if let Err(err) = fs::write(path, archive?) {
+            return Err(format!("Writing checkpoint failed: {}", err));
+        }
+        Ok(())
+    }
+
+    fn restore(&self, path: &Path) -> Result<String, String> {
+        let archive = match fs::read(path) {
+            Ok(archive) => archive,
+            Err(err) => return Err(format!("Reading checkpoint failed: {}", err)),
+        };
+        self.api.restore(&archive, &restored_name())
+    }
+
+    fn kill(&self, id: &str) -> Result<(), String> {
+        self.api.kill(id)
+    }
+
+    fn list(&self, key: &str) -> Result<Vec<String>, String> {
+        self.api.list(key)
+    }
+
+    fn remove(&self, ids: &[String]) -> Result<(), String> {
+        self.api.remove(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::engine::testutil::{fake_engine, frame, tar_of, Reply};
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn creates_and_starts_container() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/create") => {
+                Reply::Json(201, json!({"Id": "abc123", "Warnings": []}))
+            }
+            ("POST", "/v4.0.0/libpod/containers/abc123/start") => Reply::Status(204),
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let id = backend.create("langbot", &[("langbot.instance", "x")]).unwrap();
+        assert_eq!(id, "abc123");
+
+        let requests = engine.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["image"], "langbot");
+        assert_eq!(body["netns"]["nsmode"], "none");
+        assert_eq!(body["labels"]["langbot.instance"], "x");
+    }
+
+    #[test]
+    fn create_reports_engine_errors() {
+        let engine = fake_engine(|_| {
+            Reply::Json(404, json!({"cause": "no such image", "message": "langbot: image not known"}))
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let err = backend.create("langbot", &[]).err().unwrap();
+        assert_eq!(err, "Creating container failed (404): langbot: image not known");
+    }
+
+    #[test]
+    fn runs_with_streaming_stdin() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/abc/exec") => Reply::Json(201, json!({"Id": "e1"})),
+            ("POST", "/v4.0.0/libpod/exec/e1/start") => Reply::Attach(Box::new(|stdin| {
+                let mut out = frame(1, b"got: ");
+                out.extend(frame(1, stdin));
+                out.extend(frame(2, b"warning"));
+                out
+            })),
+            ("GET", "/v4.0.0/libpod/exec/e1/json") => {
+                Reply::Json(200, json!({"ExitCode": 3, "Running": false}))
+            }
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let output = backend.run("abc", "python", &[], b"print(1)").unwrap();
+        assert_eq!(output.stdout, b"got: print(1)");
+        assert_eq!(output.stderr, b"warning");
+        assert_eq!(output.status.code(), Some(3));
+
+        let requests = engine.requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["Cmd"], json!(["./scripts/run.sh", "python"]));
+        assert_eq!(body["AttachStdin"], true);
+    }
+
+    #[test]
+    fn killed_exec_keeps_output() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/abc/exec") => Reply::Json(201, json!({"Id": "e1"})),
+            ("POST", "/v4.0.0/libpod/exec/e1/start") => {
+                Reply::Attach(Box::new(|_| frame(1, b"partial")))
+            }
+            // The container and its exec sessions are gone
+            ("GET", "/v4.0.0/libpod/exec/e1/json") => {
+                Reply::Json(404, json!({"message": "no such exec session"}))
+            }
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let output = backend.run("abc", "python", &[], b"while True: print(1)").unwrap();
+        assert_eq!(output.stdout, b"partial");
+        assert_eq!(output.status.code(), Some(137));
+    }
+
+    #[test]
+    fn waits_for_exec_to_finish() {
+        let inspections = AtomicUsize::new(0);
+        let engine = fake_engine(move |req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/abc/exec") => Reply::Json(201, json!({"Id": "e1"})),
+            ("POST", "/v4.0.0/libpod/exec/e1/start") => Reply::Attach(Box::new(|_| Vec::new())),
+            // The output can end before the engine notices that the process is done
+            ("GET", "/v4.0.0/libpod/exec/e1/json") => match inspections.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Reply::Json(200, json!({"ExitCode": 0, "Running": true})),
+                _ => Reply::Json(200, json!({"ExitCode": 4, "Running": false})),
+            },
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let output = backend.run("abc", "python", &[], b"").unwrap();
+        assert_eq!(output.status.code(), Some(4));
+        assert_eq!(engine.requests.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn failed_inspection_is_an_error() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/abc/exec") => Reply::Json(201, json!({"Id": "e1"})),
+            ("POST", "/v4.0.0/libpod/exec/e1/start") => Reply::Attach(Box::new(|_| Vec::new())),
+            ("GET", "/v4.0.0/libpod/exec/e1/json") => {
+                Reply::Json(500, json!({"message": "database is locked"}))
+            }
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let err = backend.run("abc", "python", &[], b"").err().unwrap();
+        assert_eq!(err, "Inspecting exec session failed (500): database is locked");
+    }
+
+    #[test]
+    fn resets_as_root() {
+        let engine = fake_engine(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/v4.0.0/libpod/containers/abc/exec") => Reply::Json(201, json!({"Id": "e1"})),
+            ("POST", "/v4.0.0/libpod/exec/e1/start") => {
+                Reply::Attach(Box::new(|_| frame(2, b"Leftover processes:")))
+            }
+            ("GET", "/v4.0.0/libpod/exec/e1/json") => Reply::Json(200, json!({"ExitCode": 1})),
+            _ => Reply::Status(500),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let err = backend.reset("abc").err().unwrap();
+        assert_eq!(err, "Resetting container failed: Leftover processes:");
+
+        let requests = engine.requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["Cmd"], json!(["langbot-reset"]));
+        assert_eq!(body["User"], "root");
+        assert_eq!(body["AttachStdin"], false);
+    }
+
+    #[test]
+    fn files_strips_wd_prefix() {
+        let engine = fake_engine(|req| {
+            if req.path == "/v4.0.0/libpod/containers/abc/archive?path=/app/deploy/c/wd" {
+                Reply::Raw(200, tar_of(&[("wd/out.txt", b"hello")]))
+            } else {
+                Reply::Status(404)
+            }
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        let raw = backend.files("abc", "c").unwrap();
+        let mut archive = tar::Archive::new(&raw[..]);
+        let mut entries = archive.entries().unwrap();
+        let mut ent = entries.next().unwrap().unwrap();
+        assert_eq!(ent.path().unwrap().to_str(), Some("out.txt"));
+        let mut content = String::new();
+        ent.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "hello");
+        assert!(entries.next().is_none());
+
+        assert!(backend.files("abc", "python").unwrap().is_empty());
+    }
+
+    #[test]
+    fn inspects_image_ids() {
+        let engine = fake_engine(|req| match req.path.as_str() {
+            "/v4.0.0/libpod/images/langbot/json" => Reply::Json(200, json!({"Id": "sha256:abc"})),
+            _ => Reply::Json(404, json!({"message": "image not known"})),
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        assert_eq!(backend.image_id("langbot").unwrap(), "sha256:abc");
+        assert!(backend.image_id("langbot-lean").is_err());
+    }
+
+    #[test]
+    fn lists_labelled_containers() {
+        let engine = fake_engine(|req| {
+            if req.path.starts_with("/v4.0.0/libpod/containers/json?all=true&filters=") {
+                Reply::Json(200, json!([{"Id": "a"}, {"Id": "b"}]))
+            } else {
+                Reply::Status(500)
+            }
+        });
+
+        let backend = LibpodBackend::new(&engine.socket, Limits::default());
+        assert_eq!(backend.list("langbot.instance").unwrap(), vec!["a", "b"]);
+    }
+}
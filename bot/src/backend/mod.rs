@@ -0,0 +1,269 @@
+//! Backends create, run code in and destroy containers.
+//! The pod manager only talks to containers through the `Backend` trait.
+
+mod cli;
+mod docker;
+mod engine;
+mod libpod;
+mod sandbox;
+mod seccomp;
+mod unixhttp;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time;
+
+use tracing::{info, warn};
+
+pub use cli::CliBackend;
+pub use docker::DockerBackend;
+pub use libpod::LibpodBackend;
+pub use sandbox::{SandboxBackend, Tool};
+
+/// Where the app lives inside of the image.
+pub const APP_DIR: &str = "/app";
+
+/// Resource limits applied to every container.
+#[derive(Clone, Default)]
+pub struct Limits {
+    /// Memory limit in bytes, swap included
+    pub memory: Option<u64>,
+    /// Maximum number of processes
+    pub pids: Option<i64>,
+    /// Number of CPUs worth of CPU time
+    pub cpus: Option<f64>,
+}
+
+/// Parse sizes like `512m` or `1g` into bytes.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim().to_ascii_lowercase();
+    let (num, mult) = match s.chars().last()? {
+        'k' => (&s[..s.len() - 1], 1024),
+        'm' => (&s[..s.len() - 1], 1024 * 1024),
+        'g' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (&s[..], 1),
+    };
+    num.parse::<u64>().ok().map(|n| n * mult)
+}
+
+impl Limits {
+    /// Read limits from `LANGBOT_MEMORY_LIMIT` (e.g `512m`), `LANGBOT_PIDS_LIMIT`
+    /// and `LANGBOT_CPUS` (e.g `0.5`). Unset variables mean no limit.
+    pub fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|val| !val.is_empty());
+        Self {
+            memory: var("LANGBOT_MEMORY_LIMIT").map(|val| {
+                parse_size(&val).unwrap_or_else(|| panic!("Invalid LANGBOT_MEMORY_LIMIT: {}", val))
+            }),
+            pids: var("LANGBOT_PIDS_LIMIT").map(|val| {
+                val.parse().unwrap_or_else(|_| panic!("Invalid LANGBOT_PIDS_LIMIT: {}", val))
+            }),
+            cpus: var("LANGBOT_CPUS").map(|val| {
+                val.parse().unwrap_or_else(|_| panic!("Invalid LANGBOT_CPUS: {}", val))
+            }),
+        }
+    }
+}
+
+pub struct ExecOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Check that the container engine is reachable.
+    fn ping(&self) -> Result<(), String>;
+
+    /// Create and start a container from the image `tag` which just idles,
+    /// without network access, with the backend's resource limits and with the given labels.
+    /// Returns the container ID.
+    /// The container should be removed automatically once it's killed.
+    fn create(&self, tag: &str, labels: &[(&str, &str)]) -> Result<String, String>;
+
+    /// The ID of the image `tag` refers to, which changes whenever the image is rebuilt.
+    fn image_id(&self, tag: &str) -> Result<String, String> {
+        let _ = tag;
+        Err(format!("The {} backend doesn't have image IDs", self.name()))
+    }
+
+    /// Run `scripts/run.sh <language> <args>...` in the container,
+    /// with `stdin` as its standard input.
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String>;
+
+    /// Get a tar archive of the files in the language's `wd` directory,
+    /// or an empty vector if there are none.
+    fn files(&self, id: &str, language: &str) -> Result<Vec<u8>, String>;
+
+    /// Run the language's hello world example in the container, discarding the output,
+    /// so that the language's files are in the page cache. Succeeds without doing anything
+    /// if the language has no example.
+    fn prime(&self, id: &str, language: &str) -> Result<(), String>;
+
+    /// Run `scripts/self-test.sh` with `languages`, or every language if it's empty,
+    /// and return its report.
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String>;
+
+    /// Record the state of a freshly started container, for `reset` to go back to.
+    fn snapshot(&self, id: &str) -> Result<(), String>;
+
+    /// Kill every process in the container and undo the changes to its file system
+    /// since `snapshot`, so that it can run another program.
+    /// Fails if the container couldn't be made clean, in which case it must not be reused.
+    fn reset(&self, id: &str) -> Result<(), String>;
+
+    /// Run `scripts/prewarm.sh <language>` in the container, then checkpoint it with CRIU
+    /// into an archive at `path`. The container is removed afterwards.
+    fn checkpoint(&self, id: &str, language: &str, path: &Path) -> Result<(), String> {
+        let _ = (id, language, path);
+        Err(format!("The {} backend doesn't support checkpoints", self.name()))
+    }
+
+    /// Create and start a new container from a checkpoint archive made by `checkpoint`.
+    /// Returns the container ID.
+    fn restore(&self, path: &Path) -> Result<String, String> {
+        let _ = path;
+        Err(format!("The {} backend doesn't support checkpoints", self.name()))
+    }
+
+    fn kill(&self, id: &str) -> Result<(), String>;
+
+    /// List the IDs of all containers, running or not, which have the label `key`,
+    /// or the label with the value if `key` is like `key=value`.
+    fn list(&self, key: &str) -> Result<Vec<String>, String>;
+
+    /// Forcibly remove containers.
+    fn remove(&self, ids: &[String]) -> Result<(), String>;
+}
+
+/// A unique name for a container restored from a checkpoint,
+/// since it would get the name of the checkpointed container otherwise.
+fn restored_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "langbot-restored-{:x}-{:x}-{}",
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Engines return archives of a directory with the directory itself as the root entry.
+/// Turn that into an archive of the directory's content, like `get-files.sh` produces.
+fn strip_archive_root(raw: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = tar::Archive::new(raw);
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(err) => return Err(format!("Reading archive failed: {}", err)),
+    };
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for ent in entries {
+        let mut ent = match ent {
+            Ok(ent) => ent,
+            Err(err) => return Err(format!("Reading archive failed: {}", err)),
+        };
+
+        let path = match ent.path() {
+            Ok(path) => path.into_owned(),
+            Err(err) => return Err(format!("Invalid file name: {}", err)),
+        };
+        let path: PathBuf = path.components().skip(1).collect();
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let mut header = ent.header().clone();
+        if let Err(err) = builder.append_data(&mut header, &path, &mut ent) {
+            return Err(format!("Writing archive failed: {}", err));
+        }
+    }
+
+    match builder.into_inner() {
+        Ok(raw) => Ok(raw),
+        Err(err) => Err(format!("Writing archive failed: {}", err)),
+    }
+}
+
+fn sandbox_from_env(tool: Tool, limits: Limits) -> SandboxBackend {
+    let var = |name: &str| env::var(name).ok().filter(|val| !val.is_empty()).map(PathBuf::from);
+    let root = var("LANGBOT_SANDBOX_ROOT").unwrap_or_else(|| PathBuf::from(".."));
+    let mut backend = SandboxBackend::new(tool, root, limits)
+        .home(var("LANGBOT_SANDBOX_HOME"))
+        .seccomp(var("LANGBOT_SECCOMP"));
+    if let Some(rootfs) = var("LANGBOT_SANDBOX_ROOTFS") {
+        backend = backend.rootfs(rootfs);
+    }
+    if let Some(base) = var("LANGBOT_SANDBOX_DIR") {
+        backend = backend.base(base);
+    }
+
+    // Nothing runs without a seccomp filter
+    if let Err(err) = backend.seccomp_filter() {
+        panic!("No seccomp filter for the sandbox: {}", err);
+    }
+    backend
+}
+
+fn default_podman_socket() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => PathBuf::from(dir).join("podman/podman.sock"),
+        Err(_) => PathBuf::from("/run/podman/podman.sock"),
+    }
+}
+
+/// Pick a backend based on `LANGBOT_BACKEND`:
+/// `cli` spawns podman processes, `libpod` talks to the podman socket
+/// (`LANGBOT_PODMAN_SOCKET`), `docker` talks to the Docker socket (`LANGBOT_DOCKER_SOCKET`).
+/// `bwrap` and `nsjail` run the deploy tree in `LANGBOT_SANDBOX_ROOT` on the host.
+/// By default, the libpod backend is used if the podman socket is reachable,
+/// then the docker backend if the Docker socket is reachable,
+/// with the CLI backend as a fallback.
+pub fn from_env() -> Arc<dyn Backend> {
+    let podman_socket = env::var("LANGBOT_PODMAN_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| default_podman_socket());
+    let docker_socket = env::var("LANGBOT_DOCKER_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/var/run/docker.sock"));
+    let limits = Limits::from_env();
+
+    let backend: Arc<dyn Backend> = match env::var("LANGBOT_BACKEND").as_deref() {
+        Ok("cli") => Arc::new(CliBackend::new(limits)),
+        Ok("libpod") => Arc::new(LibpodBackend::new(podman_socket, limits)),
+        Ok("docker") => Arc::new(DockerBackend::new(docker_socket, limits)),
+        Ok("bwrap") => Arc::new(sandbox_from_env(Tool::Bwrap, limits)),
+        Ok("nsjail") => Arc::new(sandbox_from_env(Tool::Nsjail, limits)),
+        Ok(other) => panic!("Unknown LANGBOT_BACKEND: {}", other),
+        Err(_) => {
+            let libpod = LibpodBackend::new(podman_socket, limits.clone());
+            let docker = DockerBackend::new(docker_socket, limits.clone());
+            match (libpod.ping(), docker.ping()) {
+                (Ok(()), _) => Arc::new(libpod),
+                (Err(_), Ok(())) => Arc::new(docker),
+                (Err(err), Err(_)) => {
+                    warn!(error = %err, "Podman socket unreachable, falling back to the CLI backend");
+                    Arc::new(CliBackend::new(limits))
+                }
+            }
+        }
+    };
+
+    info!(backend = backend.name(), "Using {} backend", backend.name());
+    backend
+}
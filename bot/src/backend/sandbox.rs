@@ -0,0 +1,664 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time;
+
+use tracing::warn;
+
+use super::{seccomp, Backend, ExecOutput, Limits, APP_DIR};
+
+const DIR_PREFIX: &str = "langbot-sandbox-";
+
+/// Limits for whatever `LANGBOT_*` leaves unset, since a sandbox would get all of the host
+/// otherwise.
+const DEFAULT_LIMITS: Limits = Limits {
+    memory: Some(512 * 1024 * 1024),
+    pids: Some(256),
+    cpus: Some(1.0),
+};
+
+/// Limits for every process in the sandbox, which cgroups don't cover, as prlimit options:
+/// file size, open files, CPU seconds and core dumps.
+const RLIMITS: &[(&str, u64)] = &[
+    ("fsize", 64 * 1024 * 1024),
+    ("nofile", 1024),
+    ("cpu", 60),
+    ("core", 0),
+];
+
+/// System directories which are made available read-only inside the sandbox.
+const SYSTEM_DIRS: &[&str] = &["usr", "bin", "sbin", "lib", "lib32", "lib64", "etc", "opt"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Bwrap,
+    Nsjail,
+}
+
+/// Runs the prebuilt `deploy/` tree directly on the host, without a container engine,
+/// by executing `scripts/run.sh <lang>` inside bubblewrap or nsjail.
+///
+/// A "container" is a directory holding the `wd` directory which gets mounted into the sandbox,
+/// so that files can be collected after the program has exited.
+/// Every run gets its own fresh mount, network and PID namespaces.
+pub struct SandboxBackend {
+    tool: Tool,
+    /// The directory containing `scripts/` and `deploy/`
+    root: PathBuf,
+    /// The directory containing the system directories, usually `/`
+    rootfs: PathBuf,
+    /// Where the per-container directories are created; preferably a tmpfs
+    base: PathBuf,
+    /// Made available read-only as `$HOME` if set, otherwise `$HOME` is a fresh tmpfs
+    home: Option<PathBuf>,
+    /// A compiled BPF program for bwrap, or a kafel policy for nsjail.
+    /// The built-in filter is used if it's not set, see `seccomp`.
+    seccomp: Option<PathBuf>,
+    /// The built-in filter, written to `base` the first time it's needed
+    builtin_seccomp: OnceLock<Result<PathBuf, String>>,
+    limits: Limits,
+    /// PIDs of the sandbox processes running in each container
+    running: Mutex<HashMap<String, HashSet<u32>>>,
+}
+
+impl SandboxBackend {
+    pub fn new(tool: Tool, root: PathBuf, limits: Limits) -> Self {
+        let base = if Path::new("/dev/shm").is_dir() {
+            PathBuf::from("/dev/shm")
+        } else {
+            std::env::temp_dir()
+        };
+
+        Self {
+            tool,
+            root,
+            rootfs: PathBuf::from("/"),
+            base,
+            home: None,
+            seccomp: None,
+            builtin_seccomp: OnceLock::new(),
+            limits: Limits {
+                memory: limits.memory.or(DEFAULT_LIMITS.memory),
+                pids: limits.pids.or(DEFAULT_LIMITS.pids),
+                cpus: limits.cpus.or(DEFAULT_LIMITS.cpus),
+            },
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn rootfs(mut self, rootfs: PathBuf) -> Self {
+        self.rootfs = rootfs;
+        self
+    }
+
+    pub fn base(mut self, base: PathBuf) -> Self {
+        self.base = base;
+        self
+    }
+
+    pub fn home(mut self, home: Option<PathBuf>) -> Self {
+        self.home = home;
+        self
+    }
+
+    pub fn seccomp(mut self, seccomp: Option<PathBuf>) -> Self {
+        self.seccomp = seccomp;
+        self
+    }
+
+    /// The language's hello world example, picked like `scripts/prime.sh` does.
+    fn example(&self, language: &str) -> Result<Option<Vec<u8>>, String> {
+        let langdir = self.root.join("langs").join(language);
+        let machine = std::env::consts::ARCH;
+        let candidates = [
+            "hello-world.txt".to_string(),
+            format!("hello-world.{}.txt", machine),
+            format!("hello-world.Linux-{}.txt", machine),
+        ];
+        let example = match candidates.iter().map(|name| langdir.join(name)).find(|p| p.is_file()) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        match fs::read(&example) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) => Err(format!("Reading {} failed: {}", example.display(), err)),
+        }
+    }
+
+    fn dir(&self, id: &str) -> PathBuf {
+        self.base.join(format!("{}{}", DIR_PREFIX, id))
+    }
+
+    fn tool_name(&self) -> &'static str {
+        match self.tool {
+            Tool::Bwrap => "bwrap",
+            Tool::Nsjail => "nsjail",
+        }
+    }
+
+    /// Mounts as (source on the host, destination in the sandbox, writable).
+    /// `deploy/<language>` is a tmpfs, so that run.sh can write its input files,
+    /// with the deployed files bound read-only into it.
+    fn mounts(&self, dir: &Path, language: &str) -> Vec<(PathBuf, String, bool)> {
+        let mut mounts = Vec::new();
+        for name in SYSTEM_DIRS {
+            let src = self.rootfs.join(name);
+            if src.exists() {
+                mounts.push((src, format!("/{}", name), false));
+            }
+        }
+
+        if let Some(home) = &self.home {
+            mounts.push((home.clone(), home.to_string_lossy().to_string(), false));
+        }
+
+        mounts.push((self.root.join("scripts"), format!("{}/scripts", APP_DIR), false));
+        mounts.push((self.root.join("deploy"), format!("{}/deploy", APP_DIR), false));
+
+        let langdir = self.root.join("deploy").join(language);
+        let dest = format!("{}/deploy/{}", APP_DIR, language);
+        if let Ok(entries) = fs::read_dir(&langdir) {
+            for ent in entries.flatten() {
+                let name = ent.file_name().to_string_lossy().to_string();
+                if name != "wd" {
+                    mounts.push((ent.path(), format!("{}/{}", dest, name), false));
+                }
+            }
+        }
+
+        mounts.push((dir.join("wd"), format!("{}/wd", dest), true));
+        mounts
+    }
+
+    /// Properties of the systemd scope the sandbox runs in, which enforce the limits.
+    fn scope_properties(&self) -> Vec<String> {
+        let mut properties = Vec::new();
+        if let Some(memory) = self.limits.memory {
+            properties.push(format!("MemoryMax={}", memory));
+            properties.push("MemorySwapMax=0".to_string());
+        }
+        if let Some(pids) = self.limits.pids {
+            properties.push(format!("TasksMax={}", pids));
+        }
+        if let Some(cpus) = self.limits.cpus {
+            properties.push(format!("CPUQuota={}%", (cpus * 100.0).round() as i64));
+        }
+        properties
+    }
+
+    /// A command which runs `program` in a transient systemd scope with the limits,
+    /// i.e in a cgroup of its own, which everything the program starts ends up in too,
+    /// and with the RLIMITS. The scope is started with `--scope`, so the program keeps
+    /// our PID and stdio.
+    fn launch(&self, program: &str) -> Command {
+        let mut cmd = Command::new("systemd-run");
+        cmd.arg("--user").arg("--scope").arg("--quiet").arg("--collect");
+        for property in self.scope_properties() {
+            cmd.arg("--property").arg(property);
+        }
+
+        cmd.arg("--").arg("prlimit");
+        for (resource, limit) in RLIMITS {
+            cmd.arg(format!("--{}={}", resource, limit));
+        }
+        cmd.arg("--").arg(program);
+        cmd
+    }
+
+    /// The seccomp filter to run with: the configured one if it can be read,
+    /// or else the built-in one. Fails rather than letting anything run without one.
+    pub fn seccomp_filter(&self) -> Result<PathBuf, String> {
+        let seccomp = match &self.seccomp {
+            Some(seccomp) => seccomp,
+            None => {
+                let builtin = self.builtin_seccomp.get_or_init(|| self.write_builtin_seccomp());
+                return builtin.clone();
+            }
+        };
+        match fs::File::open(seccomp) {
+            Ok(file) if file.metadata().is_ok_and(|meta| meta.is_file()) => Ok(seccomp.clone()),
+            Ok(_) => Err(format!("Seccomp filter {} isn't a file", seccomp.display())),
+            Err(err) => Err(format!("Reading seccomp filter {} failed: {}", seccomp.display(), err)),
+        }
+    }
+
+    fn write_builtin_seccomp(&self) -> Result<PathBuf, String> {
+        let (filter, ext) = match self.tool {
+            Tool::Bwrap => (seccomp::bpf()?, "bpf"),
+            Tool::Nsjail => (seccomp::kafel()?.into_bytes(), "kafel"),
+        };
+
+        // Written under a temporary name first, so a sandbox never sees half of it
+        let path = self.base.join(format!("langbot-seccomp-{}.{}", std::process::id(), ext));
+        let tmp = path.with_extension("tmp");
+        match fs::write(&tmp, filter).and_then(|_| fs::rename(&tmp, &path)) {
+            Ok(()) => Ok(path),
+            Err(err) => Err(format!("Writing seccomp filter failed: {}", err)),
+        }
+    }
+
+    fn command(&self, dir: &Path, language: &str, seccomp: &Path) -> Command {
+        let langdest = format!("{}/deploy/{}", APP_DIR, language);
+        let home = match &self.home {
+            Some(home) => home.to_string_lossy().to_string(),
+            None => "/tmp".to_string(),
+        };
+        let path = "/usr/local/bin:/usr/bin:/bin:/usr/games";
+
+        let mut cmd;
+        match self.tool {
+            Tool::Bwrap => {
+                // bwrap wants the seccomp program on a file descriptor,
+                // so let bash open it for us
+                cmd = self.launch("bash");
+                cmd.arg("-c")
+                    .arg("exec bwrap --seccomp 3 \"$@\" 3<\"$0\"")
+                    .arg(seccomp);
+
+                cmd.arg("--unshare-all")
+                    .arg("--die-with-parent")
+                    .arg("--new-session")
+                    .arg("--clearenv")
+                    .arg("--setenv").arg("PATH").arg(path)
+                    .arg("--setenv").arg("HOME").arg(&home)
+                    .arg("--setenv").arg("LANG").arg("C.UTF-8")
+                    .arg("--proc").arg("/proc")
+                    .arg("--dev").arg("/dev")
+                    .arg("--tmpfs").arg("/tmp");
+                for (src, dest, writable) in self.mounts(dir, language) {
+                    if dest == format!("{}/deploy", APP_DIR) {
+                        cmd.arg("--ro-bind").arg(&src).arg(&dest);
+                        cmd.arg("--tmpfs").arg(&langdest);
+                        continue;
+                    }
+                    cmd.arg(if writable { "--bind" } else { "--ro-bind" })
+                        .arg(&src)
+                        .arg(&dest);
+                }
+                cmd.arg("--chdir").arg(APP_DIR);
+            }
+            Tool::Nsjail => {
+                cmd = self.launch("nsjail");
+                cmd.arg("--mode").arg("o")
+                    .arg("--quiet")
+                    .arg("--time_limit").arg("0")
+                    // Keep the RLIMITS set by prlimit rather than nsjail's defaults;
+                    // memory, processes and CPU time are limited by the scope
+                    .arg("--rlimit_as").arg("soft")
+                    .arg("--rlimit_nproc").arg("soft")
+                    .arg("--rlimit_cpu").arg("soft")
+                    .arg("--rlimit_fsize").arg("soft")
+                    .arg("--rlimit_nofile").arg("soft")
+                    .arg("--rlimit_stack").arg("soft")
+                    .arg("--env").arg(format!("PATH={}", path))
+                    .arg("--env").arg(format!("HOME={}", home))
+                    .arg("--env").arg("LANG=C.UTF-8")
+                    .arg("--tmpfsmount").arg("/tmp");
+                cmd.arg("--seccomp_policy").arg(seccomp);
+                for (src, dest, writable) in self.mounts(dir, language) {
+                    if dest == format!("{}/deploy", APP_DIR) {
+                        cmd.arg("--bindmount_ro").arg(format!("{}:{}", src.display(), dest));
+                        cmd.arg("--tmpfsmount").arg(&langdest);
+                        continue;
+                    }
+                    let flag = if writable { "--bindmount" } else { "--bindmount_ro" };
+                    cmd.arg(flag).arg(format!("{}:{}", src.display(), dest));
+                }
+                cmd.arg("--cwd").arg(APP_DIR).arg("--");
+            }
+        }
+
+        cmd.arg("/bin/bash").arg("./scripts/run.sh").arg(language);
+        cmd
+    }
+}
+
+impl Backend for SandboxBackend {
+    fn name(&self) -> &'static str {
+        self.tool_name()
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        let probe = if self.tool == Tool::Bwrap { "--version" } else { "--help" };
+        let probes = [
+            (self.tool_name(), probe),
+            ("systemd-run", "--version"),
+            ("prlimit", "--version"),
+        ];
+        for (program, arg) in probes {
+            let status = Command::new(program)
+                .arg(arg)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            match status {
+                Ok(status) if status.success() => (),
+                Ok(status) => return Err(format!("Running {} failed: {}", program, status)),
+                Err(err) => return Err(format!("Running {} failed: {}", program, err)),
+            }
+        }
+
+        self.seccomp_filter()?;
+
+        if !self.root.join("deploy").is_dir() {
+            return Err(format!("{} has no deploy directory", self.root.display()));
+        }
+
+        Ok(())
+    }
+
+    fn create(&self, _tag: &str, labels: &[(&str, &str)]) -> Result<String, String> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let id = format!(
+            "{:x}-{:x}-{}",
+            std::process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let dir = self.dir(&id);
+        if let Err(err) = fs::create_dir_all(dir.join("wd")) {
+            return Err(format!("Creating sandbox directory failed: {}", err));
+        }
+
+        // Labels are stored on disk, so that a new instance can find orphaned sandboxes
+        let labels: String = labels
+            .iter()
+            .map(|(key, val)| format!("{}={}\n", key, val))
+            .collect();
+        if let Err(err) = fs::write(dir.join("labels"), labels) {
+            let _ = fs::remove_dir_all(&dir);
+            return Err(format!("Writing labels failed: {}", err));
+        }
+
+        self.running.lock().unwrap().insert(id.clone(), HashSet::new());
+        Ok(id)
+    }
+
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        let dir = self.dir(id);
+        if !dir.is_dir() {
+            return Err(format!("No such sandbox: {}", id));
+        }
+        let seccomp = self.seccomp_filter()?;
+
+        let child = self
+            .command(&dir, language, &seccomp)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return Err(format!("Running program failed: {}", err)),
+        };
+
+        let pid = child.id();
+        match self.running.lock().unwrap().get_mut(id) {
+            Some(pids) => {
+                pids.insert(pid);
+            }
+            None => {
+                // Killed before we got started
+                let _ = child.kill();
+            }
+        }
+
+        if let Some(mut child_stdin) = child.stdin.take() {
+            if let Err(err) = child_stdin.write_all(stdin) {
+                warn!(error = %err, "Writing stdin failed");
+            }
+        }
+
+        let output = child.wait_with_output();
+        if let Some(pids) = self.running.lock().unwrap().get_mut(id) {
+            pids.remove(&pid);
+        }
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => return Err(format!("Running program failed: {}", err)),
+        };
+
+        Ok(ExecOutput {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn files(&self, id: &str, _language: &str) -> Result<Vec<u8>, String> {
+        let wd = self.dir(id).join("wd");
+        let output = Command::new("tar")
+            .arg("c")
+            .arg("-C")
+            .arg(&wd)
+            .arg(".")
+            .stdin(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => Ok(output.stdout),
+            Ok(output) => Err(format!(
+                "Getting files failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )),
+            Err(err) => Err(format!("Getting files failed: {}", err)),
+        }
+    }
+
+    /// Does what `scripts/prime.sh` does, with the examples from `langs/` in the root.
+    fn prime(&self, id: &str, language: &str) -> Result<(), String> {
+        let content = match self.example(language)? {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+
+        let output = self.run(id, language, &[], &content)?;
+        if !output.status.success() {
+            return Err(format!(
+                "Priming sandbox failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Does what `scripts/self-test.sh` does, with the examples from `langs/` in the root.
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        let languages = if languages.is_empty() {
+            let entries = match fs::read_dir(self.root.join("deploy")) {
+                Ok(entries) => entries,
+                Err(err) => return Err(format!("Listing languages failed: {}", err)),
+            };
+            let mut languages: Vec<String> = entries
+                .flatten()
+                .map(|ent| ent.file_name().to_string_lossy().to_string())
+                .collect();
+            languages.sort();
+            languages
+        } else {
+            languages.to_vec()
+        };
+
+        let mut report = String::new();
+        for language in languages {
+            if !self.root.join("deploy").join(&language).join(".done").is_file() {
+                report += &format!("fail {} not deployed\n", language);
+                continue;
+            }
+
+            let content = match self.example(&language)? {
+                Some(content) => content,
+                None => {
+                    report += &format!("skip {}\n", language);
+                    continue;
+                }
+            };
+
+            let output = self.run(id, &language, &[], &content)?;
+            if !output.status.success() {
+                let code = output.status.code().unwrap_or(-1);
+                report += &format!("fail {} exit code {}\n", language, code);
+            } else if String::from_utf8_lossy(&output.stdout).trim_end_matches('\n') != "Hello World" {
+                report += &format!("fail {} wrong output\n", language);
+            } else {
+                report += &format!("ok {}\n", language);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Every run gets a fresh sandbox, so there's nothing to snapshot.
+    fn snapshot(&self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Only `wd` outlives a run, so that's all there is to clean.
+    fn reset(&self, id: &str) -> Result<(), String> {
+        match self.running.lock().unwrap().get(id) {
+            Some(pids) if pids.is_empty() => (),
+            Some(_) => return Err("Resetting sandbox failed: still running".into()),
+            None => return Err(format!("No such sandbox: {}", id)),
+        }
+
+        let wd = self.dir(id).join("wd");
+        let res = fs::remove_dir_all(&wd).and_then(|_| fs::create_dir(&wd));
+        match res {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!("Resetting sandbox failed: {}", err)),
+        }
+    }
+
+    /// Kill everything running in the sandbox and remove its directory,
+    /// like a container started with --rm.
+    fn kill(&self, id: &str) -> Result<(), String> {
+        let pids = self.running.lock().unwrap().remove(id).unwrap_or_default();
+        for pid in pids {
+            // Killing the sandbox tool takes the whole PID namespace with it
+            let _ = Command::new("kill")
+                .arg("-KILL")
+                .arg(pid.to_string())
+                .stderr(Stdio::null())
+                .status();
+        }
+
+        match fs::remove_dir_all(self.dir(id)) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!("Removing sandbox failed: {}", err)),
+        }
+    }
+
+    fn list(&self, key: &str) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(&self.base) {
+            Ok(entries) => entries,
+            Err(err) => return Err(format!("Listing sandboxes failed: {}", err)),
+        };
+
+        // Either just the key, or the key and the value
+        let matches = |line: &str| match key.contains('=') {
+            true => line == key,
+            false => line.starts_with(&format!("{}=", key)),
+        };
+        let mut ids = Vec::new();
+        for ent in entries.flatten() {
+            let name = ent.file_name().to_string_lossy().to_string();
+            let id = match name.strip_prefix(DIR_PREFIX) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+
+            let labels = fs::read_to_string(ent.path().join("labels")).unwrap_or_default();
+            if labels.lines().any(matches) {
+                ids.push(id);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn remove(&self, ids: &[String]) -> Result<(), String> {
+        for id in ids {
+            if self.dir(id).exists() {
+                self.kill(id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(cmd: &Command) -> Vec<String> {
+        let mut args = vec![cmd.get_program().to_string_lossy().to_string()];
+        args.extend(cmd.get_args().map(|arg| arg.to_string_lossy().to_string()));
+        args
+    }
+
+    #[test]
+    fn limits_apply_to_a_scope() {
+        let limits = Limits {
+            memory: Some(64 * 1024 * 1024),
+            pids: None,
+            cpus: Some(0.5),
+        };
+        let backend = SandboxBackend::new(Tool::Nsjail, PathBuf::from(".."), limits);
+        let seccomp = Path::new("/policy.kafel");
+        let args = argv(&backend.command(Path::new("/nonexistent"), "c", seccomp));
+        assert_eq!(args[..4], ["systemd-run", "--user", "--scope", "--quiet"]);
+        // Unset limits get the defaults
+        for property in ["MemoryMax=67108864", "MemorySwapMax=0", "TasksMax=256", "CPUQuota=50%"] {
+            assert!(args.iter().any(|arg| arg == property), "{:?}", args);
+        }
+        for rlimit in ["--fsize=67108864", "--nofile=1024", "--cpu=60", "--core=0"] {
+            assert!(args.iter().any(|arg| arg == rlimit), "{:?}", args);
+        }
+        let tool = args.iter().position(|arg| arg == "nsjail").unwrap();
+        assert_eq!(args[tool - 1], "--");
+        let policy = args.iter().position(|arg| arg == "--seccomp_policy").unwrap();
+        assert_eq!(args[policy + 1], "/policy.kafel");
+    }
+
+    #[test]
+    fn refuses_to_run_without_seccomp_filter() {
+        let base = std::env::temp_dir().join(format!("langbot-sandbox-test-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let backend = SandboxBackend::new(Tool::Bwrap, PathBuf::from(".."), Limits::default())
+            .base(base.clone())
+            .seccomp(Some(base.join("missing.bpf")));
+
+        let id = backend.create("langbot", &[]).unwrap();
+        let err = backend.run(&id, "shell", &[], b"echo hi").err().unwrap();
+        assert!(err.starts_with("Reading seccomp filter"), "{}", err);
+
+        // Without LANGBOT_SECCOMP, the built-in filter is used
+        let backend = SandboxBackend::new(Tool::Bwrap, PathBuf::from(".."), Limits::default())
+            .base(base.clone());
+        match seccomp::bpf() {
+            Ok(filter) => {
+                let path = backend.seccomp_filter().unwrap();
+                assert_eq!(fs::read(path).unwrap(), filter);
+            }
+            Err(_) => assert!(backend.seccomp_filter().is_err()),
+        }
+        let _ = fs::remove_dir_all(&base);
+    }
+}
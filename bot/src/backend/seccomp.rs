@@ -0,0 +1,163 @@
+//! The seccomp filter sandboxes get when `LANGBOT_SECCOMP` doesn't name one.
+//! It allows everything except system calls which programs have no use for, but which
+//! reach into the kernel's less trodden paths or outside the sandbox: mounting, namespaces,
+//! tracing other processes, kernel modules, keyrings, BPF and the like.
+//! They fail with EPERM. It only exists for x86_64.
+
+/// The denied system calls, with their numbers on x86_64.
+const DENIED: &[(&str, u32)] = &[
+    ("ptrace", 101),
+    ("syslog", 103),
+    ("vhangup", 153),
+    ("pivot_root", 155),
+    ("adjtimex", 159),
+    ("acct", 163),
+    ("settimeofday", 164),
+    ("mount", 165),
+    ("umount2", 166),
+    ("swapon", 167),
+    ("swapoff", 168),
+    ("reboot", 169),
+    ("sethostname", 170),
+    ("setdomainname", 171),
+    ("iopl", 172),
+    ("ioperm", 173),
+    ("init_module", 175),
+    ("delete_module", 176),
+    ("quotactl", 179),
+    ("lookup_dcookie", 212),
+    ("clock_settime", 227),
+    ("kexec_load", 246),
+    ("add_key", 248),
+    ("request_key", 249),
+    ("keyctl", 250),
+    ("unshare", 272),
+    ("perf_event_open", 298),
+    ("name_to_handle_at", 303),
+    ("open_by_handle_at", 304),
+    ("clock_adjtime", 305),
+    ("setns", 308),
+    ("process_vm_readv", 310),
+    ("process_vm_writev", 311),
+    ("finit_module", 313),
+    ("kexec_file_load", 320),
+    ("bpf", 321),
+    ("userfaultfd", 323),
+];
+
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+/// Set in the numbers of x32 system calls, which are all denied
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const EPERM: u32 = 1;
+
+fn supported() -> Result<(), String> {
+    match std::env::consts::ARCH {
+        "x86_64" => Ok(()),
+        arch => Err(format!("There's no built-in seccomp filter for {}, set LANGBOT_SECCOMP", arch)),
+    }
+}
+
+/// The filter as a BPF program, i.e an array of `struct sock_filter`, for `bwrap --seccomp`.
+pub fn bpf() -> Result<Vec<u8>, String> {
+    supported()?;
+
+    let denied = DENIED.len() as u8;
+    // (code, jump if true, jump if false, k). Jumps count the instructions they skip.
+    let mut program = vec![
+        // seccomp_data.arch
+        (BPF_LD_W_ABS, 0, 0, 4),
+        (BPF_JEQ_K, 1, 0, AUDIT_ARCH_X86_64),
+        (BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+        // seccomp_data.nr
+        (BPF_LD_W_ABS, 0, 0, 0),
+        (BPF_JGE_K, denied + 1, 0, X32_SYSCALL_BIT),
+    ];
+    for (i, (_, nr)) in DENIED.iter().enumerate() {
+        program.push((BPF_JEQ_K, denied - i as u8, 0, *nr));
+    }
+    program.push((BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+    program.push((BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO | EPERM));
+
+    let mut raw = Vec::new();
+    for (code, jt, jf, k) in program {
+        raw.extend(code.to_ne_bytes());
+        raw.extend([jt, jf]);
+        raw.extend(k.to_ne_bytes());
+    }
+    Ok(raw)
+}
+
+/// The filter as a kafel policy, for `nsjail --seccomp_policy`.
+pub fn kafel() -> Result<String, String> {
+    supported()?;
+
+    let names: Vec<&str> = DENIED.iter().map(|(name, _)| *name).collect();
+    Ok(format!(
+        "POLICY langbot {{\n  ERRNO({}) {{\n    {}\n  }}\n}}\nUSE langbot DEFAULT ALLOW\n",
+        EPERM,
+        names.join(",\n    ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the BPF program for a system call, the way the kernel would.
+    fn verdict(raw: &[u8], arch: u32, nr: u32) -> u32 {
+        let program: Vec<(u16, u8, u8, u32)> = raw
+            .chunks(8)
+            .map(|ins| {
+                let code = u16::from_ne_bytes([ins[0], ins[1]]);
+                let k = u32::from_ne_bytes([ins[4], ins[5], ins[6], ins[7]]);
+                (code, ins[2], ins[3], k)
+            })
+            .collect();
+
+        let (mut pc, mut acc) = (0, 0);
+        loop {
+            let (code, jt, jf, k) = program[pc];
+            pc += 1;
+            match code {
+                BPF_LD_W_ABS => acc = if k == 0 { nr } else { arch },
+                BPF_JEQ_K => pc += if acc == k { jt } else { jf } as usize,
+                BPF_JGE_K => pc += if acc >= k { jt } else { jf } as usize,
+                BPF_RET_K => return k,
+                _ => panic!("Unknown instruction {:x}", code),
+            }
+        }
+    }
+
+    #[test]
+    fn denies_listed_system_calls() {
+        let raw = match bpf() {
+            Ok(raw) => raw,
+            Err(_) => return,
+        };
+
+        let errno = SECCOMP_RET_ERRNO | EPERM;
+        for (_, nr) in DENIED {
+            assert_eq!(verdict(&raw, AUDIT_ARCH_X86_64, *nr), errno, "{}", nr);
+        }
+        // read, write, execve, clone
+        for nr in [0, 1, 59, 56] {
+            assert_eq!(verdict(&raw, AUDIT_ARCH_X86_64, nr), SECCOMP_RET_ALLOW, "{}", nr);
+        }
+        assert_eq!(verdict(&raw, AUDIT_ARCH_X86_64, X32_SYSCALL_BIT | 1), errno);
+        // i386 system calls have different numbers, so they're not allowed at all
+        assert_eq!(verdict(&raw, 0x4000_0003, 1), SECCOMP_RET_KILL_PROCESS);
+
+        let policy = kafel().unwrap();
+        assert!(policy.contains("    ptrace,\n"), "{}", policy);
+        assert!(policy.ends_with("USE langbot DEFAULT ALLOW\n"), "{}", policy);
+    }
+}
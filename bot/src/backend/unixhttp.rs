@@ -0,0 +1,314 @@
+//! A minimal blocking HTTP/1.1 client for talking to container engines over unix sockets.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time;
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Turn a non-2xx response into an error, using the engine's error message if there is one.
+    pub fn error_for_status(self, what: &str) -> Result<Response, String> {
+        if self.is_success() {
+            return Ok(self);
+        }
+
+        let message = serde_json::from_slice::<serde_json::Value>(&self.body)
+            .ok()
+            .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(String::from))
+            .unwrap_or_else(|| String::from_utf8_lossy(&self.body).trim().to_string());
+        Err(format!("{} failed ({}): {}", what, self.status, message))
+    }
+}
+
+pub struct Client {
+    socket: PathBuf,
+    timeout: time::Duration,
+}
+
+impl Client {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+            timeout: time::Duration::from_secs(60),
+        }
+    }
+
+    fn connect(&self) -> Result<UnixStream, String> {
+        let stream = match UnixStream::connect(&self.socket) {
+            Ok(stream) => stream,
+            Err(err) => return Err(format!("Connecting to {} failed: {}", self.socket.display(), err)),
+        };
+
+        // A wedged engine shouldn't wedge us forever
+        let _ = stream.set_read_timeout(Some(self.timeout));
+        let _ = stream.set_write_timeout(Some(self.timeout));
+        Ok(stream)
+    }
+
+    fn send_request(
+        &self,
+        stream: &mut UnixStream,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+        upgrade: bool,
+    ) -> Result<(), String> {
+        let (content_type, body) = body.unwrap_or(("", &[]));
+
+        let mut req = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        if upgrade {
+            req += "Connection: Upgrade\r\nUpgrade: tcp\r\n";
+        } else {
+            req += "Connection: close\r\n";
+        }
+        if !content_type.is_empty() {
+            req += &format!("Content-Type: {}\r\n", content_type);
+        }
+        req += &format!("Content-Length: {}\r\n\r\n", body.len());
+
+        let mut buf = req.into_bytes();
+        buf.extend_from_slice(body);
+        match stream.write_all(&buf) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!("Sending request failed: {}", err)),
+        }
+    }
+
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response, String> {
+        let body = body.map(|body| serde_json::to_vec(body).unwrap());
+        self.request_raw(
+            method,
+            path,
+            body.as_deref().map(|body| ("application/json", body)),
+        )
+    }
+
+    /// Like `request`, but with a body of any content type, given as (content type, body).
+    pub fn request_raw(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+    ) -> Result<Response, String> {
+        let mut stream = self.connect()?;
+        self.send_request(&mut stream, method, path, body, false)?;
+
+        let mut raw = Vec::new();
+        if let Err(err) = stream.read_to_end(&mut raw) {
+            return Err(format!("Reading response failed: {}", err));
+        }
+
+        parse_response(&raw)
+    }
+
+    /// Send a request which hijacks the connection, like exec start with attach.
+    /// Returns the response status, the stream and anything read past the headers.
+    pub fn upgrade(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<(u16, UnixStream, Vec<u8>), String> {
+        let body = body.map(|body| serde_json::to_vec(body).unwrap());
+        let mut stream = self.connect()?;
+        self.send_request(
+            &mut stream,
+            method,
+            path,
+            body.as_deref().map(|body| ("application/json", body)),
+            true,
+        )?;
+
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let header_end = loop {
+            if let Some(pos) = find_header_end(&raw) {
+                break pos;
+            }
+
+            let n = match stream.read(&mut buf) {
+                Ok(0) => return Err("Connection closed before response headers".into()),
+                Ok(n) => n,
+                Err(err) => return Err(format!("Reading response failed: {}", err)),
+            };
+            raw.extend_from_slice(&buf[..n]);
+        };
+
+        let (status, _) = parse_head(&raw[..header_end])?;
+        let rest = raw[header_end..].to_vec();
+        Ok((status, stream, rest))
+    }
+}
+
+fn find_header_end(raw: &[u8]) -> Option<usize> {
+    raw.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4)
+}
+
+fn parse_head(head: &[u8]) -> Result<(u16, Vec<(String, String)>), String> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("Invalid status line: {:?}", status_line))?;
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    Ok((status, headers))
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, String> {
+    let header_end = match find_header_end(raw) {
+        Some(pos) => pos,
+        None => return Err("Truncated response".into()),
+    };
+
+    let (status, headers) = parse_head(&raw[..header_end])?;
+    let body = &raw[header_end..];
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let body = if header("transfer-encoding") == Some("chunked") {
+        decode_chunked(body)?
+    } else if let Some(len) = header("content-length").and_then(|len| len.parse::<usize>().ok()) {
+        body[..len.min(body.len())].to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = match body.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => pos,
+            None => return Err("Truncated chunked body".into()),
+        };
+
+        let size_str = String::from_utf8_lossy(&body[..line_end]);
+        let size_str = size_str.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size_str, 16) {
+            Ok(size) => size,
+            Err(_) => return Err(format!("Invalid chunk size: {:?}", size_str)),
+        };
+
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+
+        if body.len() < size {
+            return Err("Truncated chunked body".into());
+        }
+
+        out.extend_from_slice(&body[..size]);
+        body = &body[(size + 2).min(body.len())..];
+    }
+}
+
+/// Split a multiplexed attach stream (8 byte header: stream type, 3 padding bytes,
+/// big endian u32 length) into stdout and stderr.
+pub fn demux(mut raw: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    while !raw.is_empty() {
+        if raw.len() < 8 {
+            return Err("Truncated stream frame header".into());
+        }
+
+        let size = u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]) as usize;
+        let payload = &raw[8..(8 + size).min(raw.len())];
+        match raw[0] {
+            1 => stdout.extend_from_slice(payload),
+            2 => stderr.extend_from_slice(payload),
+            _ => (),
+        }
+        raw = &raw[(8 + size).min(raw.len())..];
+    }
+
+    Ok((stdout, stderr))
+}
+
+/// Percent-encode a string for use in a query parameter.
+pub fn encode_query(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            _ => out += &format!("%{:02X}", b),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_content_length_response() {
+        let raw = b"HTTP/1.1 201 Created\r\nContent-Length: 11\r\n\r\n{\"Id\":\"ab\"}";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 201);
+        assert_eq!(resp.body, b"{\"Id\":\"ab\"}");
+    }
+
+    #[test]
+    fn parses_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n6\r\n World\r\n0\r\n\r\n";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"Hello World");
+    }
+
+    #[test]
+    fn demuxes_streams() {
+        let mut raw = vec![1, 0, 0, 0, 0, 0, 0, 3];
+        raw.extend_from_slice(b"out");
+        raw.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 3]);
+        raw.extend_from_slice(b"err");
+        raw.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 1]);
+        raw.extend_from_slice(b"!");
+        let (stdout, stderr) = demux(&raw).unwrap();
+        assert_eq!(stdout, b"out!");
+        assert_eq!(stderr, b"err");
+    }
+
+    #[test]
+    fn error_uses_engine_message() {
+        let resp = Response {
+            status: 404,
+            body: b"{\"cause\":\"no such container\",\"message\":\"no container with name or ID abc\"}"
+                .to_vec(),
+        };
+        let err = resp.error_for_status("Killing container").err().unwrap();
+        assert_eq!(err, "Killing container failed (404): no container with name or ID abc");
+    }
+}
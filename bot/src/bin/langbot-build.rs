@@ -0,0 +1,128 @@
+//! Builds languages into `deploy/`, replacing `scripts/compile-all.sh`: languages are built
+//! after the languages they depend on, and only rebuilt when their cache key changes.
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time;
+
+use langbot::build::{self, BuildResult, Dirs, Status};
+
+const USAGE: &str =
+    "Usage: langbot-build [--jobs N] [--only LANGUAGES] [--base-digest DIGEST] [--root DIR]";
+
+struct Options {
+    jobs: usize,
+    only: Vec<String>,
+    base_digest: String,
+    root: PathBuf,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        jobs: 8,
+        only: Vec::new(),
+        base_digest: env::var("LANGBOT_BASE_DIGEST").unwrap_or_default(),
+        root: PathBuf::from("."),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--jobs" | "-j" => {
+                let jobs = value(&arg)?;
+                opts.jobs = match jobs.parse() {
+                    Ok(jobs) if jobs > 0 => jobs,
+                    _ => return Err(format!("Invalid --jobs: {}", jobs)),
+                };
+            }
+            // Separated by commas or spaces, so that it can be passed a build arg as is
+            "--only" => {
+                let only = value(&arg)?;
+                let names = only.split(|ch: char| ch == ',' || ch.is_whitespace());
+                opts.only.extend(names.filter(|name| !name.is_empty()).map(String::from));
+            }
+            "--base-digest" => opts.base_digest = value(&arg)?,
+            "--root" => opts.root = PathBuf::from(value(&arg)?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(opts)
+}
+
+fn describe(status: &Status) -> (&'static str, String) {
+    match status {
+        Status::Built => ("built", String::new()),
+        Status::Cached => ("cached", String::new()),
+        Status::Failed(err) => ("FAILED", err.clone()),
+        Status::Skipped(why) => ("skipped", why.clone()),
+    }
+}
+
+fn main() {
+    let opts = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let dirs = Dirs::new(&opts.root);
+    let langs = build::load(&dirs.langs)
+        .and_then(|langs| build::plan(langs, &opts.only))
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(2);
+        });
+
+    if opts.base_digest.is_empty() {
+        eprintln!(
+            "WARNING: No --base-digest, so languages won't be rebuilt when the base image changes"
+        );
+    }
+
+    eprintln!("Building {} languages with {} jobs", langs.len(), opts.jobs);
+    let start = time::Instant::now();
+    let results = build::build_all(langs, dirs, &opts.base_digest, opts.jobs, |result| {
+        let (status, detail) = describe(&result.status);
+        // Failures come with the end of their log, which is only printed here
+        eprintln!("=== {}: {} ({:.1}s) {}", status, result.name, result.elapsed.as_secs_f64(), detail);
+    });
+
+    let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0).max("LANGUAGE".len());
+    let mut sorted: Vec<&BuildResult> = results.iter().collect();
+    sorted.sort_by_key(|result| std::cmp::Reverse(result.elapsed));
+
+    println!();
+    println!("{:width$}  {:7}  {:>8}", "LANGUAGE", "STATUS", "TIME", width = width);
+    let mut failures = 0;
+    for result in sorted {
+        let (status, detail) = describe(&result.status);
+        if matches!(result.status, Status::Failed(_) | Status::Skipped(_)) {
+            failures += 1;
+        }
+        println!(
+            "{:width$}  {:7}  {:>7.1}s  {}",
+            result.name,
+            status,
+            result.elapsed.as_secs_f64(),
+            detail.lines().next().unwrap_or(""),
+            width = width
+        );
+    }
+    println!();
+    println!(
+        "{} languages, {} failed, in {:.1}s",
+        results.len(),
+        failures,
+        start.elapsed().as_secs_f64()
+    );
+
+    if failures > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,123 @@
+//! Runs the conformance tests of languages through the same backend as the bot:
+//! every language's hello world example, and the cases in `langs/<lang>/tests/*.toml`.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+
+use langbot::backend;
+use langbot::routes::Router;
+use langbot::testsuite::{self, Case, Outcome};
+
+const USAGE: &str = "Usage: langbot-test [--jobs N] [--junit FILE] [--langs DIR] [LANGUAGE...]";
+
+struct Options {
+    jobs: usize,
+    junit: Option<PathBuf>,
+    langs_dir: PathBuf,
+    languages: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        jobs: thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+        junit: None,
+        langs_dir: PathBuf::from("../langs"),
+        languages: Vec::new(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--jobs" | "-j" => {
+                let jobs = value(&arg)?;
+                opts.jobs = match jobs.parse() {
+                    Ok(jobs) if jobs > 0 => jobs,
+                    _ => return Err(format!("Invalid --jobs: {}", jobs)),
+                };
+            }
+            "--junit" => opts.junit = Some(PathBuf::from(value(&arg)?)),
+            "--langs" => opts.langs_dir = PathBuf::from(value(&arg)?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => opts.languages.push(arg),
+        }
+    }
+
+    Ok(opts)
+}
+
+fn main() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("error"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let opts = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let cases = Case::load_all(&opts.langs_dir, &opts.languages).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+
+    let backend = backend::from_env();
+    let router = Router::from_env();
+    println!("Running {} tests with {} jobs", cases.len(), opts.jobs);
+
+    let queue = Mutex::new(VecDeque::from(cases));
+    let outcomes: Mutex<Vec<Outcome>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..opts.jobs {
+            scope.spawn(|| loop {
+                let case = match queue.lock().unwrap().pop_front() {
+                    Some(case) => case,
+                    None => return,
+                };
+
+                let outcome = case.run(backend.clone(), router.image(&case.language));
+                if outcome.passed() {
+                    println!("   ok: {}/{} ({:.1}s)", outcome.language, outcome.name, outcome.elapsed.as_secs_f64());
+                } else {
+                    println!(" FAIL: {}/{}", outcome.language, outcome.name);
+                    for problem in &outcome.problems {
+                        println!("       {}", problem);
+                    }
+                }
+                outcomes.lock().unwrap().push(outcome);
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by(|a, b| (&a.language, &a.name).cmp(&(&b.language, &b.name)));
+
+    if let Some(path) = &opts.junit {
+        if let Err(err) = fs::write(path, testsuite::junit(&outcomes)) {
+            eprintln!("Writing {} failed: {}", path.display(), err);
+            process::exit(2);
+        }
+    }
+
+    let failed: Vec<&Outcome> = outcomes.iter().filter(|o| !o.passed()).collect();
+    println!();
+    println!("{} passed, {} failed", outcomes.len() - failed.len(), failed.len());
+    for outcome in &failed {
+        println!("  {}/{}: {}", outcome.language, outcome.name, outcome.problems.join("; "));
+    }
+    if !failed.is_empty() {
+        process::exit(1);
+    }
+}
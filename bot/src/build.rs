@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time;
+
+use sha2::{Digest, Sha256};
+
+use crate::manifest::Manifest;
+
+/// How much of the log of a failed `compile.sh` goes in its error
+const LOG_TAIL_LINES: usize = 20;
+
+/// A language to build, from `langs/<name>`.
+pub struct Language {
+    pub name: String,
+    pub dir: PathBuf,
+    pub depends: Vec<String>,
+}
+
+pub enum Status {
+    /// Built from scratch
+    Built,
+    /// The cache key matched what's in the staging directory
+    Cached,
+    Failed(String),
+    /// Not built because a dependency failed
+    Skipped(String),
+}
+
+pub struct BuildResult {
+    pub name: String,
+    pub status: Status,
+    pub elapsed: time::Duration,
+}
+
+/// Where the build happens. Languages are compiled in `work`, installed into `staging`,
+/// which is kept between builds as a cache, and copied from there to `deploy`.
+/// The logs are kept in `staging` too, so that they outlive the image build.
+pub struct Dirs {
+    pub langs: PathBuf,
+    pub work: PathBuf,
+    pub staging: PathBuf,
+    pub deploy: PathBuf,
+    pub logs: PathBuf,
+}
+
+impl Dirs {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            langs: root.join("langs"),
+            work: root.join("work"),
+            staging: root.join("staging"),
+            deploy: root.join("deploy"),
+            logs: root.join("staging").join(".logs"),
+        }
+    }
+}
+
+/// Load every language in `langs_dir`.
+pub fn load(langs_dir: &Path) -> Result<Vec<Language>, String> {
+    let paths = fs::read_dir(langs_dir)
+        .map_err(|err| format!("Reading {} failed: {}", langs_dir.display(), err))?;
+
+    let mut langs = Vec::new();
+    for path in paths.flatten() {
+        let dir = path.path();
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let manifest = Manifest::load(&dir)?;
+        langs.push(Language {
+            name: path.file_name().to_string_lossy().to_string(),
+            dir,
+            depends: manifest.depends,
+        });
+    }
+
+    langs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(langs)
+}
+
+/// Sort languages so that every language comes after its dependencies,
+/// and keep only those in `only` and what they depend on, unless it's empty.
+pub fn plan(langs: Vec<Language>, only: &[String]) -> Result<Vec<Language>, String> {
+    let mut by_name: HashMap<String, Language> =
+        langs.into_iter().map(|lang| (lang.name.clone(), lang)).collect();
+    for lang in by_name.values() {
+        for dep in &lang.depends {
+            if !by_name.contains_key(dep) {
+                return Err(format!("{} depends on {}, which doesn't exist", lang.name, dep));
+            }
+        }
+    }
+
+    let mut wanted: Vec<String> = match only.is_empty() {
+        true => by_name.keys().cloned().collect(),
+        false => only.to_vec(),
+    };
+    wanted.sort();
+
+    // Depth first, so that dependencies end up first
+    fn visit(
+        name: &str,
+        by_name: &HashMap<String, Language>,
+        visiting: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if visiting.iter().any(|n| n == name) {
+            visiting.push(name.to_string());
+            return Err(format!("Dependency cycle: {}", visiting.join(" -> ")));
+        }
+
+        let lang = match by_name.get(name) {
+            Some(lang) => lang,
+            None => return Err(format!("Unknown language: {}", name)),
+        };
+        visiting.push(name.to_string());
+        for dep in &lang.depends {
+            visit(dep, by_name, visiting, done, order)?;
+        }
+        visiting.pop();
+
+        done.insert(name.to_string());
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    let mut done = HashSet::new();
+    let mut order = Vec::new();
+    for name in &wanted {
+        visit(name, &by_name, &mut Vec::new(), &mut done, &mut order)?;
+    }
+
+    Ok(order
+        .into_iter()
+        .map(|name| by_name.remove(&name).unwrap())
+        .collect())
+}
+
+/// Add every file under `dir` to the hash, in a stable order.
+/// Hello world examples and tests don't affect the build, so they're left out.
+fn hash_dir(hasher: &mut Sha256, root: &Path, dir: &Path) -> io::Result<()> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|ent| ent.map(|ent| ent.path()))
+        .collect::<io::Result<_>>()?;
+    paths.sort();
+
+    for path in paths {
+        let rel = path.strip_prefix(root).unwrap().to_string_lossy().to_string();
+        if rel == "tests" || (rel.starts_with("hello-world.") && rel.ends_with(".txt")) {
+            continue;
+        }
+
+        if path.is_dir() {
+            hash_dir(hasher, root, &path)?;
+        } else {
+            let content = fs::read(&path)?;
+            hasher.update(format!("{}\0{}\0", rel, content.len()));
+            hasher.update(&content);
+        }
+    }
+
+    Ok(())
+}
+
+/// The cache key of a language: a hash of its files, the base image,
+/// and the cache keys of its dependencies.
+pub fn cache_key(lang: &Language, base_digest: &str, dep_keys: &[&str]) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("base {}\0", base_digest));
+    for key in dep_keys {
+        hasher.update(format!("dep {}\0", key));
+    }
+    hash_dir(&mut hasher, &lang.dir, &lang.dir)
+        .map_err(|err| format!("Reading {} failed: {}", lang.dir.display(), err))?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn run(cmd: &mut Command) -> Result<(), String> {
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{:?} failed: {}", cmd, status)),
+        Err(err) => Err(format!("{:?} failed: {}", cmd, err)),
+    }
+}
+
+fn remove_dir(path: &Path) -> Result<(), String> {
+    match fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(format!("Removing {} failed: {}", path.display(), err)),
+    }
+}
+
+/// Copy `src` to `dest`, which mustn't exist, keeping permissions and symlinks.
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), String> {
+    run(Command::new("cp").arg("-a").arg(src).arg(dest))
+}
+
+/// The last `lines` lines of the file at `path`, or nothing if it can't be read.
+fn tail(path: &Path, lines: usize) -> String {
+    let text = fs::read(path).unwrap_or_default();
+    let text = String::from_utf8_lossy(&text);
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+/// Compile a language into its staging directory, unless it's already there with the same
+/// cache key. Output from `compile.sh` goes to `staging/.logs/<lang>.log`.
+fn compile(lang: &Language, key: &str, dirs: &Dirs) -> Result<Status, String> {
+    let staging = dirs.staging.join(&lang.name);
+    let key_file = staging.join(".cachekey");
+    if fs::read_to_string(&key_file).ok().as_deref() == Some(key) {
+        return Ok(Status::Cached);
+    }
+
+    let work = dirs.work.join(&lang.name);
+    remove_dir(&work)?;
+    fs::create_dir_all(&dirs.work).map_err(|err| format!("Creating work dir failed: {}", err))?;
+    copy_dir(&lang.dir, &work)?;
+
+    remove_dir(&staging)?;
+    fs::create_dir_all(&staging).map_err(|err| format!("Creating staging dir failed: {}", err))?;
+    for name in ["run.sh", "prewarm.sh"] {
+        if work.join(name).exists() {
+            fs::copy(work.join(name), staging.join(name))
+                .map_err(|err| format!("Copying {} failed: {}", name, err))?;
+        }
+    }
+
+    if work.join("compile.sh").exists() {
+        let log_path = dirs.logs.join(format!("{}.log", lang.name));
+        let log = fs::File::create(&log_path)
+            .map_err(|err| format!("Creating {} failed: {}", log_path.display(), err))?;
+        let log_err = log.try_clone().map_err(|err| format!("Opening log failed: {}", err))?;
+        let status = Command::new("bash")
+            .args(["-x", "-euo", "pipefail", "./compile.sh"])
+            .current_dir(&work)
+            .env("DEPLOYDIR", &staging)
+            .stdin(Stdio::null())
+            .stdout(log)
+            .stderr(log_err)
+            .status()
+            .map_err(|err| format!("Running compile.sh failed: {}", err))?;
+        if !status.success() {
+            return Err(format!(
+                "compile.sh failed: {}, see {}\n{}",
+                status,
+                log_path.display(),
+                tail(&log_path, LOG_TAIL_LINES)
+            ));
+        }
+    } else {
+        fs::write(staging.join(".done"), "").map_err(|err| format!("Creating .done failed: {}", err))?;
+    }
+
+    // compile.sh scripts should produce a .done file as a sanity check
+    if !staging.join(".done").exists() {
+        return Err("compile.sh didn't produce a '$DEPLOYDIR/.done' file".to_string());
+    }
+
+    fs::write(&key_file, key).map_err(|err| format!("Writing cache key failed: {}", err))?;
+    Ok(Status::Built)
+}
+
+/// Build a language and copy it to its deploy directory.
+fn build_one(lang: &Language, key: &str, dirs: &Dirs) -> Result<Status, String> {
+    if !lang.dir.join("run.sh").exists() {
+        return Err(format!("Missing file: {}", lang.dir.join("run.sh").display()));
+    }
+
+    let status = compile(lang, key, dirs)?;
+
+    let deploy = dirs.deploy.join(&lang.name);
+    remove_dir(&deploy)?;
+    fs::create_dir_all(&dirs.deploy).map_err(|err| format!("Creating deploy dir failed: {}", err))?;
+    copy_dir(&dirs.staging.join(&lang.name), &deploy)?;
+    Ok(status)
+}
+
+/// Build `langs`, which must be in the order from `plan`, running up to `jobs` builds at once.
+/// A language is only built once all of its dependencies are. `on_done` is called
+/// as each language finishes.
+pub fn build_all(
+    langs: Vec<Language>,
+    dirs: Dirs,
+    base_digest: &str,
+    jobs: usize,
+    mut on_done: impl FnMut(&BuildResult),
+) -> Vec<BuildResult> {
+    if let Err(err) = fs::create_dir_all(&dirs.logs) {
+        eprintln!("Creating {} failed: {}", dirs.logs.display(), err);
+    }
+
+    let dirs = Arc::new(dirs);
+    let mut pending: Vec<Language> = langs;
+    let mut keys: HashMap<String, String> = HashMap::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    let mut results = Vec::new();
+    let mut running = 0;
+    let (done_send, done_recv) = mpsc::channel::<(BuildResult, Option<String>)>();
+
+    loop {
+        // Start everything whose dependencies are done, in order
+        let mut i = 0;
+        while i < pending.len() && running < jobs {
+            let lang = &pending[i];
+            if let Some(dep) = lang.depends.iter().find(|dep| failed.contains(*dep)).cloned() {
+                let lang = pending.remove(i);
+                let result = BuildResult {
+                    name: lang.name.clone(),
+                    status: Status::Skipped(format!("{} failed", dep)),
+                    elapsed: time::Duration::ZERO,
+                };
+                failed.insert(lang.name);
+                on_done(&result);
+                results.push(result);
+                continue;
+            }
+            if !lang.depends.iter().all(|dep| keys.contains_key(dep)) {
+                i += 1;
+                continue;
+            }
+
+            let lang = pending.remove(i);
+            let dep_keys: Vec<&str> = lang.depends.iter().map(|dep| keys[dep].as_str()).collect();
+            let key = cache_key(&lang, base_digest, &dep_keys);
+            let dirs = dirs.clone();
+            let done_send = done_send.clone();
+            running += 1;
+            thread::spawn(move || {
+                let start = time::Instant::now();
+                let res = key.and_then(|key| build_one(&lang, &key, &dirs).map(|status| (status, key)));
+                let (status, key) = match res {
+                    Ok((status, key)) => (status, Some(key)),
+                    Err(err) => (Status::Failed(err), None),
+                };
+                let result = BuildResult {
+                    name: lang.name,
+                    status,
+                    elapsed: start.elapsed(),
+                };
+                let _ = done_send.send((result, key));
+            });
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let (result, key) = done_recv.recv().unwrap();
+        running -= 1;
+        match key {
+            Some(key) => {
+                keys.insert(result.name.clone(), key);
+            }
+            None => {
+                failed.insert(result.name.clone());
+            }
+        }
+        on_done(&result);
+        results.push(result);
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lang(name: &str, depends: &[&str]) -> Language {
+        Language {
+            name: name.to_string(),
+            dir: PathBuf::from(name),
+            depends: depends.iter().map(|dep| dep.to_string()).collect(),
+        }
+    }
+
+    fn names(langs: &[Language]) -> Vec<&str> {
+        langs.iter().map(|lang| lang.name.as_str()).collect()
+    }
+
+    #[test]
+    fn plans_dependencies_first() {
+        let langs = || vec![lang("barrel", &["racket"]), lang("c", &[]), lang("racket", &[])];
+        assert_eq!(names(&plan(langs(), &[]).unwrap()), vec!["racket", "barrel", "c"]);
+        assert_eq!(names(&plan(langs(), &["barrel".to_string()]).unwrap()), vec!["racket", "barrel"]);
+
+        let err = plan(vec![lang("a", &["b"]), lang("b", &["a"])], &[]).err().unwrap();
+        assert_eq!(err, "Dependency cycle: a -> b -> a");
+        assert!(plan(vec![lang("a", &["x"])], &[]).is_err());
+        assert!(plan(langs(), &["lean".to_string()]).is_err());
+    }
+
+    #[test]
+    fn tails_logs() {
+        let path = std::env::temp_dir().join(format!("langbot-build-log-{}", std::process::id()));
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(tail(&path, 2), "two\nthree");
+        assert_eq!(tail(&path, 5), "one\ntwo\nthree");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tail(&path, 2), "");
+    }
+}
@@ -0,0 +1,337 @@
+//! Remembers the results of programs in languages which always give the same result
+//! for the same program, so that reposting a snippet or editing a message back and forth
+//! doesn't run it again.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::backend::parse_size;
+use crate::manifest::Manifest;
+use crate::podmanager::{ExecResult, Input};
+
+/// A hash of the language, the source, the timeout and the image.
+pub type Key = [u8; 32];
+
+struct Entry {
+    stdout: Option<String>,
+    stderr: Option<String>,
+    status: ExitStatus,
+    /// The raw tar archive of the files
+    files: Option<Vec<u8>>,
+    stored: time::Instant,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        let len = |text: &Option<String>| text.as_ref().map_or(0, |text| text.len());
+        len(&self.stdout) + len(&self.stderr) + self.files.as_ref().map_or(0, |files| files.len())
+    }
+
+    fn to_result(&self, cached: bool) -> ExecResult {
+        ExecResult {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            status: self.status,
+            files: self
+                .files
+                .clone()
+                .map(|files| Arc::new(Mutex::new(tar::Archive::new(Cursor::new(files))))),
+            timed_out: false,
+            cached,
+        }
+    }
+}
+
+struct Entries {
+    lru: LruCache<Key, Entry>,
+    /// The total size of the entries
+    size: usize,
+}
+
+/// Results in memory, least recently used first out.
+pub struct ResultCache {
+    /// Languages whose manifest says they're deterministic
+    deterministic: HashSet<String>,
+    /// Results older than this aren't used
+    ttl: time::Duration,
+    /// The least recently used results are dropped when they take up more than this many bytes
+    max_size: usize,
+    entries: Mutex<Entries>,
+}
+
+/// The languages in `langs` which are declared deterministic in their `language.toml`.
+/// Languages with broken manifests aren't.
+pub fn deterministic_languages(langs: &Path) -> Vec<String> {
+    let dirs = match fs::read_dir(langs) {
+        Ok(dirs) => dirs,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut languages = Vec::new();
+    for dir in dirs.flatten() {
+        match Manifest::load(&dir.path()) {
+            Ok(manifest) if manifest.deterministic => {
+                languages.push(dir.file_name().to_string_lossy().to_string());
+            }
+            Ok(_) => (),
+            Err(err) => warn!(error = %err, "Invalid manifest, not caching its results"),
+        }
+    }
+
+    languages.sort();
+    languages
+}
+
+impl ResultCache {
+    pub fn new(
+        deterministic: impl IntoIterator<Item = String>,
+        ttl: time::Duration,
+        max_size: usize,
+    ) -> Self {
+        Self {
+            deterministic: deterministic.into_iter().collect(),
+            ttl,
+            max_size,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    /// Configure the cache from `LANGBOT_CACHE_SIZE` (e.g `64m`), which enables it,
+    /// and `LANGBOT_CACHE_TTL` (in seconds, default an hour), for the deterministic
+    /// languages in `../langs`.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|val| !val.is_empty());
+        let max_size = var("LANGBOT_CACHE_SIZE").map(|val| {
+            parse_size(&val).unwrap_or_else(|| panic!("Invalid LANGBOT_CACHE_SIZE: {}", val))
+        })?;
+        let ttl = var("LANGBOT_CACHE_TTL").map_or(60 * 60, |val| {
+            val.parse().unwrap_or_else(|_| panic!("Invalid LANGBOT_CACHE_TTL: {}", val))
+        });
+
+        let languages = deterministic_languages(Path::new("../langs"));
+        Some(Self::new(languages, time::Duration::from_secs(ttl), max_size as usize))
+    }
+
+    /// The key for running `content` as `language` with `input` and `timeout` in the image
+    /// with the ID `image`, see `PodManager::image_id`.
+    /// Results in languages which aren't deterministic aren't cached, so they have no key.
+    pub fn key(
+        &self,
+        language: &str,
+        content: &str,
+        input: &Input,
+        timeout: time::Duration,
+        image: &str,
+    ) -> Option<Key> {
+        if !self.deterministic.contains(language) {
+            return None;
+        }
+
+        let mut hasher = Sha256::new();
+        for part in [language, image] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher.update((timeout.as_millis() as u64).to_le_bytes());
+        hasher.update(Sha256::digest(content));
+        hasher.update(Sha256::digest(&input.stdin));
+        hasher.update((input.args.len() as u64).to_le_bytes());
+        for arg in &input.args {
+            hasher.update((arg.len() as u64).to_le_bytes());
+            hasher.update(arg);
+        }
+        Some(hasher.finalize().into())
+    }
+
+    /// The result stored for `key`, unless it's older than the TTL.
+    pub fn get(&self, key: &Key, now: time::Instant) -> Option<ExecResult> {
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = match entries.lru.get(key) {
+            Some(entry) => now.duration_since(entry.stored) < self.ttl,
+            None => return None,
+        };
+        if !fresh {
+            let entry = entries.lru.pop(key).unwrap();
+            entries.size -= entry.size();
+            return None;
+        }
+
+        entries.lru.get(key).map(|entry| entry.to_result(true))
+    }
+
+    /// Store `output` for `key`, unless the program timed out.
+    /// Reading the files of `output` consumes them, so use the result this returns instead.
+    pub fn put(&self, key: Key, output: ExecResult, now: time::Instant) -> ExecResult {
+        if output.timed_out {
+            return output;
+        }
+
+        let files = match output.files {
+            Some(archive) => match Arc::try_unwrap(archive) {
+                Ok(archive) => Some(archive.into_inner().unwrap().into_inner().into_inner()),
+                // Someone else has a hold of the archive, so they might have read it
+                Err(archive) => {
+                    return ExecResult {
+                        files: Some(archive),
+                        ..output
+                    }
+                }
+            },
+            None => None,
+        };
+        let entry = Entry {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            status: output.status,
+            files,
+            stored: now,
+        };
+        let result = entry.to_result(false);
+
+        let size = entry.size();
+        if size > self.max_size {
+            return result;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(old) = entries.lru.put(key, entry) {
+            entries.size -= old.size();
+        }
+        entries.size += size;
+        while entries.size > self.max_size {
+            match entries.lru.pop_lru() {
+                Some((_, old)) => entries.size -= old.size(),
+                None => break,
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn result(stdout: &str, files: Option<Vec<u8>>) -> ExecResult {
+        ExecResult {
+            stdout: Some(stdout.to_string()),
+            stderr: None,
+            status: ExitStatus::from_raw(0),
+            files: files.map(|files| Arc::new(Mutex::new(tar::Archive::new(Cursor::new(files))))),
+            timed_out: false,
+            cached: false,
+        }
+    }
+
+    fn archive(name: &str, content: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn caches_deterministic_results() {
+        let minute = time::Duration::from_secs(60);
+        let cache = ResultCache::new(["c".to_string()], minute, 1024 * 1024);
+        let timeout = time::Duration::from_secs(3);
+        let none = Input::default();
+        let key = cache.key("c", "main", &none, timeout, "sha256:1").unwrap();
+        assert_eq!(cache.key("python", "main", &none, timeout, "sha256:1"), None);
+        assert_ne!(cache.key("c", "main", &none, timeout, "sha256:2"), Some(key));
+        assert_ne!(cache.key("c", "main", &none, minute, "sha256:1"), Some(key));
+        assert_ne!(cache.key("c", "main2", &none, timeout, "sha256:1"), Some(key));
+        let stdin = Input {
+            stdin: "1".to_string(),
+            args: Vec::new(),
+        };
+        assert_ne!(cache.key("c", "main", &stdin, timeout, "sha256:1"), Some(key));
+        let args = |args: &[&str]| Input {
+            stdin: String::new(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        let split = cache.key("c", "main", &args(&["a", "b"]), timeout, "sha256:1");
+        assert_ne!(split, cache.key("c", "main", &args(&["ab"]), timeout, "sha256:1"));
+
+        let now = time::Instant::now();
+        assert!(cache.get(&key, now).is_none());
+        let output = cache.put(key, result("hi", Some(archive("out.txt", "file"))), now);
+        assert!(!output.cached);
+        assert_eq!(output.read_files().unwrap()[0].content, b"file");
+
+        // Every hit gets its own copy of the files
+        for _ in 0..2 {
+            let hit = cache.get(&key, now + minute / 2).unwrap();
+            assert!(hit.cached);
+            assert_eq!(hit.stdout.as_deref(), Some("hi"));
+            assert_eq!(hit.read_files().unwrap()[0].name, "out.txt");
+        }
+
+        assert!(cache.get(&key, now + minute).is_none());
+        assert_eq!(cache.entries.lock().unwrap().size, 0);
+
+        let timed_out = ExecResult {
+            timed_out: true,
+            ..result("slow", None)
+        };
+        cache.put(key, timed_out, now);
+        assert!(cache.get(&key, now).is_none());
+    }
+
+    #[test]
+    fn finds_deterministic_languages() {
+        let langs = std::env::temp_dir().join(format!("langbot-cache-{}", std::process::id()));
+        for (language, manifest) in [
+            ("pure", "deterministic = true\n"),
+            ("clock", "deterministic = false\n"),
+            ("plain", ""),
+            ("broken", "deterministic = 1\n"),
+        ] {
+            fs::create_dir_all(langs.join(language)).unwrap();
+            fs::write(langs.join(language).join("language.toml"), manifest).unwrap();
+        }
+        fs::create_dir_all(langs.join("none")).unwrap();
+
+        let languages = deterministic_languages(&langs);
+        fs::remove_dir_all(&langs).unwrap();
+        assert_eq!(languages, ["pure"]);
+    }
+
+    #[test]
+    fn drops_least_recently_used_results() {
+        let cache = ResultCache::new(["c".to_string()], time::Duration::from_secs(60), 10);
+        let timeout = time::Duration::from_secs(3);
+        let key = |code: &str| cache.key("c", code, &Input::default(), timeout, "sha256:1").unwrap();
+        let now = time::Instant::now();
+
+        cache.put(key("a"), result("aaaa", None), now);
+        cache.put(key("b"), result("bbbb", None), now);
+        assert!(cache.get(&key("a"), now).is_some());
+        cache.put(key("c"), result("cccc", None), now);
+        assert!(cache.get(&key("a"), now).is_some());
+        assert!(cache.get(&key("b"), now).is_none());
+        assert!(cache.get(&key("c"), now).is_some());
+
+        // Results which don't fit at all aren't stored
+        cache.put(key("d"), result("dddddddddddd", None), now);
+        assert!(cache.get(&key("d"), now).is_none());
+        assert!(cache.get(&key("c"), now).is_some());
+    }
+}
@@ -0,0 +1,280 @@
+//! Subcommands of the bot's binary, for working on languages without connecting to Discord.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use langbot::backend;
+use langbot::discord;
+use langbot::lint::{self, Severity};
+use langbot::podmanager::PodManager;
+use langbot::routes::Router;
+use langbot::scaffold::{self, Template};
+use serde_json::json;
+
+/// Run the subcommand in `args`, if there is one, and return its exit code.
+pub fn run(args: &[String]) -> Option<i32> {
+    let (command, args) = args.split_first()?;
+    match command.as_str() {
+        "lint" => Some(lint(args)),
+        "new-lang" => Some(new_lang(args)),
+        "run" => Some(run_snippet(args)),
+        _ => None,
+    }
+}
+
+/// langbot lint [LANGS_DIR]
+fn lint(args: &[String]) -> i32 {
+    let langs_dir = Path::new(args.first().map(String::as_str).unwrap_or("../langs"));
+    let problems = match lint::lint_all(langs_dir) {
+        Ok(problems) => problems,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+
+    let mut errors = 0;
+    for (lang, problem) in &problems {
+        let severity = match problem.severity {
+            Severity::Error => {
+                errors += 1;
+                "error"
+            }
+            Severity::Warning => "warning",
+        };
+        println!("{}: {}: {}", lang, severity, problem.message);
+    }
+
+    println!("{} errors, {} warnings", errors, problems.len() - errors);
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// langbot new-lang <NAME> [--template interpreted|compiled|git-source] [--langs LANGS_DIR]
+fn new_lang(args: &[String]) -> i32 {
+    const USAGE: &str =
+        "Usage: langbot new-lang <NAME> [--template interpreted|compiled|git-source] [--langs DIR]";
+
+    let mut name = None;
+    let mut template = Template::Interpreted;
+    let mut langs_dir = "../langs".to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let res = match arg.as_str() {
+            "--template" => args
+                .next()
+                .ok_or_else(|| USAGE.to_string())
+                .and_then(|value| value.parse())
+                .map(|value| template = value),
+            "--langs" => args
+                .next()
+                .ok_or_else(|| USAGE.to_string())
+                .map(|value| langs_dir = value.clone()),
+            _ if !arg.starts_with('-') && name.is_none() => {
+                name = Some(arg.clone());
+                Ok(())
+            }
+            _ => Err(USAGE.to_string()),
+        };
+        if let Err(err) = res {
+            eprintln!("{}", err);
+            return 2;
+        }
+    }
+
+    let name = match name {
+        Some(name) => name,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match scaffold::create(Path::new(&langs_dir), &name, template) {
+        Ok(paths) => {
+            for path in paths {
+                println!("Created {}", path.display());
+            }
+            println!("Next, edit run.sh and the rest, then check it with 'langbot lint' and langbot-test");
+            0
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// langbot run <LANGUAGE> [FILE] [--json] [--extract DIR]
+fn run_snippet(args: &[String]) -> i32 {
+    const USAGE: &str = "Usage: langbot run <LANGUAGE> [FILE] [--json] [--extract DIR]";
+
+    let mut positional = Vec::new();
+    let mut json = false;
+    let mut extract = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--extract" => match args.next() {
+                Some(dir) => extract = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            _ if !arg.starts_with('-') => positional.push(arg.clone()),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let (language, source) = match positional.as_slice() {
+        [language] => {
+            let mut source = String::new();
+            if let Err(err) = io::stdin().read_to_string(&mut source) {
+                eprintln!("Reading stdin failed: {}", err);
+                return 2;
+            }
+            (language.to_lowercase(), source)
+        }
+        [language, path] => match fs::read_to_string(path) {
+            Ok(source) => (language.to_lowercase(), source),
+            Err(err) => {
+                eprintln!("Reading {} failed: {}", path, err);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .init();
+
+    let podman = PodManager::standalone(backend::from_env(), Router::from_env());
+    let start = time::Instant::now();
+    let mut pod = match podman.get_pod(&language) {
+        Ok(pod) => pod,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return 1;
+        }
+    };
+    let waited = start.elapsed();
+    let container = pod.id().to_string();
+
+    let start = time::Instant::now();
+    let res = pod.execute(&language, &source);
+    let ran = start.elapsed();
+    podman.recycle(pod);
+
+    let output = match res {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return 1;
+        }
+    };
+    let files = match output.read_files() {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return 1;
+        }
+    };
+
+    if let Some(dir) = &extract {
+        for file in &files {
+            let name = Path::new(&file.name);
+            if !name.components().all(|part| matches!(part, Component::Normal(_))) {
+                eprintln!("Not extracting {}, it's outside of {}", file.name, dir.display());
+                continue;
+            }
+
+            let path = dir.join(name);
+            let res = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::write(&path, &file.content));
+            if let Err(err) = res {
+                eprintln!("Writing {} failed: {}", path.display(), err);
+                return 1;
+            }
+        }
+    }
+
+    let stdout = output.stdout.as_deref().unwrap_or("");
+    let stderr = output.stderr.as_deref().unwrap_or("");
+    if json {
+        let files: Vec<serde_json::Value> = files
+            .iter()
+            .map(|file| {
+                json!({
+                    "name": file.name,
+                    "size": file.content.len(),
+                    "content_base64": BASE64.encode(&file.content),
+                })
+            })
+            .collect();
+        let body = json!({
+            "language": language,
+            "container": container,
+            "stdout": stdout,
+            "stderr": stderr,
+            "success": output.status.success(),
+            "exit_code": output.status.code(),
+            "exit": discord::describe_exit(&output.status),
+            "wait_ms": waited.as_millis() as u64,
+            "run_ms": ran.as_millis() as u64,
+            "truncated": {
+                "stdout": discord::truncate_string(stdout) != stdout,
+                "stderr": discord::truncate_string(stderr) != stderr,
+            },
+            "files": files,
+        });
+        println!("{}", body);
+        return 0;
+    }
+
+    for (name, text) in [("STDOUT", stdout), ("STDERR", stderr)] {
+        if text.is_empty() {
+            continue;
+        }
+
+        println!("--- {} ---", name);
+        println!("{}", text);
+        if discord::truncate_string(text) != text {
+            println!("(Discord only shows the first 20 lines or 800 characters of this)");
+        }
+    }
+    if !files.is_empty() {
+        println!("--- FILES ---");
+        for file in &files {
+            println!("{} ({} bytes)", file.name, file.content.len());
+        }
+    }
+    println!("--- {} ---", discord::describe_exit(&output.status));
+    println!(
+        "Waited {:.2}s for container {}, ran in {:.2}s",
+        waited.as_secs_f64(),
+        container,
+        ran.as_secs_f64()
+    );
+    0
+}
@@ -0,0 +1,668 @@
+//! Turns Discord messages into executions, and executions into replies.
+//! Everything which talks to Discord goes through the `Discord` trait,
+//! so that the handler can be driven by something other than a real gateway connection.
+
+use std::borrow::Cow;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+use serenity::async_trait;
+use serenity::client::bridge::gateway::event::ShardStageUpdateEvent;
+use serenity::gateway::ConnectionStage;
+use serenity::http::Http;
+use serenity::model::channel::{AttachmentType, Message, MessageReference};
+use serenity::model::event::{MessageUpdateEvent, ResumedEvent};
+use serenity::model::gateway::Ready;
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+use serenity::model::user::CurrentUser;
+use serenity::prelude::*;
+use serenity::utils::Color;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+
+use crate::executor::Executor;
+use crate::languages;
+use crate::parse::ParsedRequest;
+use crate::podmanager::{ExecResult, OutputFile};
+use crate::store::{ResultStore, StoredResult};
+
+/// Cut `text` down to what fits nicely in an embed field:
+/// at most 20 lines and 800 characters, not counting " (truncated...)".
+pub fn truncate_string(text: &str) -> String {
+    const CHLIMIT: usize = 800;
+    const LINELIMIT: usize = 20;
+
+    let mut lines = text.lines();
+    let mut output = lines.by_ref().take(LINELIMIT).collect::<Vec<_>>().join("\n");
+    let mut truncated = lines.next().is_some();
+    if output.chars().count() > CHLIMIT {
+        output = output.chars().take(CHLIMIT).collect();
+        truncated = true;
+    }
+
+    if truncated {
+        format!("{} (truncated...)", output)
+    } else {
+        output
+    }
+}
+
+fn exit_code_to_desc(code: i32) -> Option<&'static str> {
+    match code {
+        126 => Some("Command not executable"),
+        127 => Some("Command not found"),
+        129 => Some("SIGHUP"),
+        130 => Some("SIGINT"),
+        131 => Some("SIGQUIT"),
+        132 => Some("SIGILL"),
+        133 => Some("SIGTRAP"),
+        134 => Some("SIGABRT"),
+        135 => Some("SIGBUS"),
+        136 => Some("SIGFPE"),
+        137 => Some("SIGKILL"),
+        139 => Some("SIGSEGV"),
+        141 => Some("SIGPIPE"),
+        143 => Some("SIGTERM"),
+        _ => None,
+    }
+}
+
+fn next_request_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn zws_encode(text: String) -> String {
+    text.replace("`", "`\u{200B}")
+}
+
+/// Like "Exit Code 0 (OK)" or "Exit Code 139 (SIGSEGV)".
+pub fn describe_exit(status: &ExitStatus) -> String {
+    if status.success() {
+        return "Exit Code 0 (OK)".to_string();
+    }
+
+    let code = match status.code() {
+        Some(code) => match exit_code_to_desc(code) {
+            Some(desc) => format!("{} ({})", code, desc),
+            None => format!("{}", code),
+        },
+        None => "Signal".to_string(),
+    };
+    format!("Exit Code {}", code)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Embed {
+    pub description: String,
+    pub color: Color,
+    /// Names and values, in order
+    pub fields: Vec<(String, String)>,
+    /// Where the full result can be seen
+    pub link: Option<String>,
+    pub footer: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+/// A message we send, or what we edit one of our messages into.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embed: Option<Embed>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// The calls the handler makes to Discord.
+#[async_trait]
+pub trait Discord: Send + Sync {
+    /// Send `text` to `channel`.
+    async fn say(&self, channel: ChannelId, text: String) -> Result<(), String>;
+
+    /// Send `reply` as a reply to the message `to`, without pinging anyone.
+    /// Returns the channel and ID of the new message.
+    async fn reply(
+        &self,
+        to: (ChannelId, MessageId),
+        reply: Reply,
+    ) -> Result<(ChannelId, MessageId), String>;
+
+    /// Edit one of our messages. The content and embed are each left alone if `reply`
+    /// has none, and the attachments are added.
+    async fn edit(&self, message: (ChannelId, MessageId), reply: Reply) -> Result<(), String>;
+}
+
+/// Talks to the real Discord through serenity.
+pub struct Serenity(pub Arc<Http>);
+
+fn create_embed(embed: &Embed) -> serenity::builder::CreateEmbed {
+    let mut create = serenity::builder::CreateEmbed::default();
+    create.description(&embed.description);
+    create.color(embed.color);
+    for (name, value) in &embed.fields {
+        create.field(name, value, false);
+    }
+    if let Some(link) = &embed.link {
+        create.title("View full result").url(link);
+    }
+    if let Some(footer) = &embed.footer {
+        create.footer(|create| create.text(footer));
+    }
+    create
+}
+
+fn attachment_type(attachment: Attachment) -> AttachmentType<'static> {
+    AttachmentType::Bytes {
+        data: Cow::Owned(attachment.data),
+        filename: attachment.filename,
+    }
+}
+
+#[async_trait]
+impl Discord for Serenity {
+    async fn say(&self, channel: ChannelId, text: String) -> Result<(), String> {
+        match channel.say(&self.0, text).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn reply(
+        &self,
+        to: (ChannelId, MessageId),
+        reply: Reply,
+    ) -> Result<(ChannelId, MessageId), String> {
+        let resp = to
+            .0
+            .send_message(&self.0, |m| {
+                m.reference_message(MessageReference::from(to));
+                m.allowed_mentions(|a| a.empty_parse());
+                if let Some(content) = &reply.content {
+                    m.content(content);
+                }
+                if let Some(embed) = &reply.embed {
+                    m.set_embed(create_embed(embed));
+                }
+                for attachment in reply.attachments {
+                    m.add_file(attachment_type(attachment));
+                }
+                m
+            })
+            .await;
+        match resp {
+            Ok(msg) => Ok((msg.channel_id, msg.id)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn edit(&self, message: (ChannelId, MessageId), reply: Reply) -> Result<(), String> {
+        let resp = message
+            .0
+            .edit_message(&self.0, message.1, |edit| {
+                if let Some(content) = &reply.content {
+                    edit.content(content);
+                }
+                if let Some(embed) = &reply.embed {
+                    edit.set_embeds(vec![create_embed(embed)]);
+                }
+                for attachment in reply.attachments {
+                    edit.attachment(attachment_type(attachment));
+                }
+                edit
+            })
+            .await;
+        match resp {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+fn embed_from_result(output: &ExecResult) -> Embed {
+    let color = match output.status.success() {
+        true => Color::DARK_GREEN,
+        false => Color::DARK_RED,
+    };
+
+    let mut fields = Vec::new();
+    if let Some(stdout) = &output.stdout {
+        fields.push((
+            "STDOUT".to_string(),
+            format!("```ansi\n{}\n```", zws_encode(truncate_string(stdout))),
+        ));
+    }
+
+    if let Some(stderr) = &output.stderr {
+        fields.push((
+            "STDERR".to_string(),
+            format!("```ansi\n{}\n```", zws_encode(truncate_string(stderr))),
+        ));
+    }
+
+    Embed {
+        description: describe_exit(&output.status),
+        color,
+        fields,
+        link: None,
+        footer: output.cached.then(|| "Cached result".to_string()),
+    }
+}
+
+fn is_output_interesting(output: &ExecResult) -> bool {
+    !output.status.success() || output.stdout.is_some() || output.stderr.is_some()
+}
+
+fn create_attachments(files: &[OutputFile]) -> Vec<Attachment> {
+    const MAX_SIZE: usize = 1024 * 1024; // 1MiB
+
+    let mut attachments = Vec::new();
+    let mut total_size = 0;
+    for file in files {
+        total_size += file.content.len();
+        if total_size > MAX_SIZE {
+            warn!(total_size, "Files too large!");
+            break;
+        }
+
+        attachments.push(Attachment {
+            filename: file.name.clone(),
+            data: file.content.clone(),
+        });
+    }
+
+    attachments
+}
+
+/// The reply to a successful execution: an embed with the output,
+/// unless the program only produced files.
+fn reply_from_result(output: &ExecResult, files: &[OutputFile], link: Option<String>) -> Reply {
+    let attachments = create_attachments(files);
+    let embed = match attachments.is_empty() || is_output_interesting(output) {
+        true => Some(Embed {
+            link,
+            ..embed_from_result(output)
+        }),
+        false => None,
+    };
+
+    Reply {
+        content: None,
+        embed,
+        attachments,
+    }
+}
+
+pub struct Handler {
+    user: Mutex<Option<CurrentUser>>,
+    executor: Arc<Executor>,
+    languages: Arc<languages::Registry>,
+    connected: Arc<AtomicBool>,
+    responses: Mutex<LruCache<(ChannelId, MessageId), (ChannelId, MessageId)>>,
+    results: Option<Arc<ResultStore>>,
+}
+
+impl Handler {
+    /// `connected` is kept up to date with the state of the gateway connection.
+    /// Results are saved to `results`, if there is one, and linked to from the replies.
+    pub fn new(
+        executor: Arc<Executor>,
+        languages: Arc<languages::Registry>,
+        connected: Arc<AtomicBool>,
+        results: Option<Arc<ResultStore>>,
+    ) -> Self {
+        Self {
+            user: Mutex::new(None),
+            executor,
+            languages,
+            connected,
+            responses: Mutex::new(LruCache::new(1024)),
+            results,
+        }
+    }
+
+    /// Set the bot's own user, which is what we get in the ready event.
+    pub fn set_user(&self, user: CurrentUser) {
+        *self.user.lock().unwrap() = Some(user);
+    }
+
+    async fn parse_and_run(
+        &self,
+        text: &str,
+    ) -> Option<(ParsedRequest, Result<ExecResult, String>)> {
+        let req = ParsedRequest::parse(text)?;
+        Span::current().record("language", &req.language.as_str());
+
+        let output = self.executor.run(req.language.clone(), req.code.clone()).await;
+        Some((req, output))
+    }
+
+    /// Save the result, unless the guild opted out, and turn it into a reply
+    /// which links to it.
+    fn create_reply(
+        &self,
+        req: &ParsedRequest,
+        output: &ExecResult,
+        guild: Option<GuildId>,
+    ) -> Reply {
+        let files = output.read_files().unwrap_or_else(|err| {
+            warn!(error = %err, "Read files error");
+            Vec::new()
+        });
+
+        let link = match &self.results {
+            Some(store) if store.accepts(guild.map(|guild| guild.0)) => {
+                match store.save(&StoredResult::new(&req.language, &req.code, output, &files)) {
+                    Ok(id) => Some(store.link(&id)),
+                    Err(err) => {
+                        warn!(error = %err, "Storing result failed");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        reply_from_result(output, &files, link)
+    }
+
+    fn does_message_mention_us(&self, msg: &Message) -> bool {
+        // If the message is a response, we don't wanna care
+        if msg.referenced_message.is_some() {
+            return false;
+        }
+
+        let me = self.user.lock().unwrap();
+        let my_id = me.as_ref().unwrap().id;
+        msg.mentions.iter().any(|m| m.id == my_id)
+    }
+
+    async fn send_usage_info(&self, discord: &dyn Discord, msg: Message) {
+        let message = {
+            let me = self.user.lock().unwrap();
+            let name = &me.as_ref().unwrap().name;
+            let mut msg = format!(
+                "I'm {}! I can be used to run code in all kinds of languages. Try this:
+```
+!language `source code`
+```
+Or this:
+```
+!language
+`\u{200B}``
+source code
+`\u{200B}``
+```",
+                name
+            );
+
+            let names = languages::Registry::names();
+            if !names.is_empty() {
+                msg += "\nI support these languages: ";
+                let mut first = true;
+                for name in names {
+                    if !first {
+                        msg += ", ";
+                    } else {
+                        first = false;
+                    }
+
+                    msg += "`";
+                    msg += &name;
+                    msg += "`";
+                }
+            }
+
+            let disabled = self.languages.disabled();
+            if !disabled.is_empty() {
+                msg += "\nThese languages are broken right now: ";
+                let reasons: Vec<String> = disabled
+                    .iter()
+                    .map(|(name, reason)| format!("`{}` ({})", name, reason))
+                    .collect();
+                msg += &reasons.join(", ");
+            }
+
+            msg += "\nFor more info, check out: <https://github.com/mortie/langbot>";
+
+            msg
+        };
+
+        if let Err(err) = discord.say(msg.channel_id, message).await {
+            warn!(error = %err, "Couldn't send message");
+        }
+    }
+
+    /// Replace the content of our reply with an error message.
+    async fn edit_error(&self, discord: &dyn Discord, response: (ChannelId, MessageId), err: String) {
+        let reply = Reply {
+            content: Some(format!("Error: {}", err)),
+            ..Default::default()
+        };
+        if let Err(err) = discord.edit(response, reply).await {
+            warn!(error = %err, "Couldn't edit message");
+        }
+    }
+
+    async fn edit_reply(&self, discord: &dyn Discord, evt: MessageUpdateEvent) {
+        let _job = self.executor.job();
+        let response = match self
+            .responses
+            .lock()
+            .unwrap()
+            .get(&(evt.channel_id, evt.id))
+        {
+            Some(response) => *response,
+            None => return,
+        };
+
+        let content = match evt.content {
+            Some(content) => content,
+            None => return,
+        };
+
+        let (req, output) = match self.parse_and_run(&content).await {
+            Some(run) => run,
+            None => return,
+        };
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                error!(error = %err, "Execution failed");
+                self.edit_error(discord, response, err).await;
+                return;
+            }
+        };
+
+        let reply = self.create_reply(&req, &output, evt.guild_id);
+        match discord.edit(response, reply).await {
+            Ok(()) => info!(reply = %response.1, "Edited reply"),
+            Err(err) => {
+                warn!(error = %err, "Couldn't edit message");
+                self.edit_error(discord, response, err).await;
+            }
+        }
+    }
+
+    async fn reply_to_message(&self, discord: &dyn Discord, msg: Message) {
+        let _job = self.executor.job();
+        let (req, output) = match self.parse_and_run(&msg.content).await {
+            Some(run) => run,
+            None => {
+                if self.does_message_mention_us(&msg) {
+                    self.send_usage_info(discord, msg).await;
+                }
+                return;
+            }
+        };
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                error!(error = %err, "Execution failed");
+                if let Err(err) = discord.say(msg.channel_id, format!("Error: {}", err)).await {
+                    warn!(error = %err, "Couldn't send error");
+                }
+                return;
+            }
+        };
+
+        let reply = self.create_reply(&req, &output, msg.guild_id);
+        let resp = discord.reply((msg.channel_id, msg.id), reply).await;
+        match resp {
+            Ok(reply) => {
+                info!(reply = %reply.1, "Sent reply");
+                self.responses
+                    .lock()
+                    .unwrap()
+                    .put((msg.channel_id, msg.id), reply);
+            }
+            Err(err) => {
+                warn!(error = %err, "Couldn't send message");
+                if let Err(err) = discord.say(msg.channel_id, format!("Error: {}", err)).await {
+                    warn!(error = %err, "Couldn't send error");
+                }
+            }
+        }
+    }
+
+    /// Run the code in a new message, and reply with the result.
+    pub async fn handle_message(&self, discord: &dyn Discord, msg: Message) {
+        // Ignore messages from bots
+        if msg.author.bot {
+            return;
+        }
+
+        let span = info_span!(
+            "request",
+            request_id = next_request_id(),
+            guild = ?msg.guild_id,
+            channel = %msg.channel_id,
+            user = %msg.author.id,
+            language = tracing::field::Empty,
+            container = tracing::field::Empty,
+        );
+        self.reply_to_message(discord, msg).instrument(span).await
+    }
+
+    /// Run the code in an edited message again, and edit our reply to it.
+    pub async fn handle_message_update(&self, discord: &dyn Discord, evt: MessageUpdateEvent) {
+        let span = info_span!(
+            "request",
+            request_id = next_request_id(),
+            guild = ?evt.guild_id,
+            channel = %evt.channel_id,
+            user = ?evt.author.as_ref().map(|author| author.id),
+            language = tracing::field::Empty,
+            container = tracing::field::Empty,
+        );
+        self.edit_reply(discord, evt).instrument(span).await
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message_update(&self, ctx: Context, evt: MessageUpdateEvent) {
+        self.handle_message_update(&Serenity(ctx.http.clone()), evt).await
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        self.handle_message(&Serenity(ctx.http.clone()), msg).await
+    }
+
+    async fn ready(&self, _: Context, ready: Ready) {
+        info!("{} is connected!", ready.user.name);
+        self.set_user(ready.user);
+        self.connected.store(true, Ordering::SeqCst);
+    }
+
+    async fn resume(&self, _: Context, _: ResumedEvent) {
+        info!("Resumed gateway connection");
+        self.connected.store(true, Ordering::SeqCst);
+    }
+
+    async fn shard_stage_update(&self, _: Context, evt: ShardStageUpdateEvent) {
+        if evt.new != ConnectionStage::Connected {
+            info!(stage = %evt.new, "Gateway connection lost");
+            self.connected.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    use proptest::prelude::*;
+
+    #[test]
+    fn truncates_output() {
+        assert_eq!(truncate_string("hello\nworld\n"), "hello\nworld");
+        assert_eq!(truncate_string("a\r\nb"), "a\nb");
+        assert_eq!(truncate_string(""), "");
+
+        let twenty: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        assert_eq!(truncate_string(&twenty.join("\n")), twenty.join("\n"));
+        let more = format!("{}\n21", twenty.join("\n"));
+        assert_eq!(truncate_string(&more), format!("{} (truncated...)", twenty.join("\n")));
+
+        assert_eq!(truncate_string(&"x".repeat(800)), "x".repeat(800));
+        assert_eq!(truncate_string(&"x".repeat(801)), format!("{} (truncated...)", "x".repeat(800)));
+
+        // The limit is in characters, not bytes
+        assert_eq!(truncate_string(&"é".repeat(800)), "é".repeat(800));
+        assert_eq!(
+            truncate_string(&"ø".repeat(1000)),
+            format!("{} (truncated...)", "ø".repeat(800))
+        );
+    }
+
+    #[test]
+    fn breaks_up_backticks() {
+        assert_eq!(zws_encode("no ticks".into()), "no ticks");
+        assert_eq!(zws_encode("```".into()), "`\u{200B}`\u{200B}`\u{200B}");
+        assert_eq!(zws_encode("`\u{200B}".into()), "`\u{200B}\u{200B}");
+    }
+
+    #[test]
+    fn describes_exits() {
+        let exit = |code: i32| describe_exit(&ExitStatus::from_raw(code << 8));
+        assert_eq!(exit(0), "Exit Code 0 (OK)");
+        assert_eq!(exit(1), "Exit Code 1");
+        assert_eq!(exit(127), "Exit Code 127 (Command not found)");
+        assert_eq!(exit(139), "Exit Code 139 (SIGSEGV)");
+        assert_eq!(describe_exit(&ExitStatus::from_raw(9)), "Exit Code Signal");
+
+        for code in 0..=255 {
+            if let Some(desc) = exit_code_to_desc(code) {
+                assert!(!desc.contains("  "), "{}", desc);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn truncated_output_fits(text in "(\\PC{0,60}\r?\n){0,30}") {
+            let output = truncate_string(&text);
+            let kept = output.strip_suffix(" (truncated...)").unwrap_or(&output);
+            prop_assert!(kept.chars().count() <= 800);
+            prop_assert!(kept.lines().count() <= 20);
+            if kept.len() == output.len() {
+                prop_assert_eq!(kept, text.lines().collect::<Vec<_>>().join("\n"));
+            }
+        }
+
+        #[test]
+        fn zws_encode_round_trips(text in "[`\u{200B}a\n]{0,20}") {
+            let encoded = zws_encode(text.clone());
+            prop_assert!(!encoded.contains("``"));
+            prop_assert_eq!(encoded.replace("`\u{200B}", "`"), text);
+        }
+    }
+}
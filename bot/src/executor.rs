@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time;
+
+use tracing::{info, warn, Span};
+
+use crate::cache::ResultCache;
+use crate::podmanager::{ExecResult, Input, PodManager, EXEC_TIMEOUT};
+
+pub const RESTARTING_MSG: &str = "The bot is restarting, try again in a little while";
+
+/// Runs code on pods from the pod manager, and keeps track of jobs
+/// so that we can shut down without cutting off running executions.
+pub struct Executor {
+    podman: Arc<PodManager>,
+    accepting: Arc<AtomicBool>,
+    jobs: Arc<AtomicUsize>,
+    cache: Option<ResultCache>,
+}
+
+/// A job is in flight for as long as this is alive.
+/// Hold on to it until the reply has been sent.
+pub struct Job(Arc<AtomicUsize>);
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Executor {
+    pub fn new(podman: Arc<PodManager>) -> Self {
+        Self {
+            podman,
+            accepting: Arc::new(AtomicBool::new(true)),
+            jobs: Arc::new(AtomicUsize::new(0)),
+            cache: None,
+        }
+    }
+
+    /// Reuse the results of deterministic languages from `cache`.
+    pub fn cache(mut self, cache: ResultCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn job(&self) -> Job {
+        self.jobs.fetch_add(1, Ordering::SeqCst);
+        Job(self.jobs.clone())
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    pub async fn run(&self, language: String, content: String) -> Result<ExecResult, String> {
+        self.run_with_timeout(language, content, Input::default(), EXEC_TIMEOUT).await
+    }
+
+    /// Like `run`, with input for the program and a different timeout.
+    pub async fn run_with_timeout(
+        &self,
+        language: String,
+        content: String,
+        input: Input,
+        timeout: time::Duration,
+    ) -> Result<ExecResult, String> {
+        if !self.is_accepting() {
+            return Err(RESTARTING_MSG.into());
+        }
+        // Results from before the language was disabled aren't to be trusted either
+        self.podman.check_enabled(&language)?;
+
+        let cached = self.cache.as_ref().and_then(|cache| {
+            let image = self.podman.image_id(&language)?;
+            let key = cache.key(&language, &content, &input, timeout, &image)?;
+            Some((cache, key))
+        });
+        if let Some((cache, key)) = &cached {
+            if let Some(output) = cache.get(key, time::Instant::now()) {
+                info!("Using cached result");
+                return Ok(output);
+            }
+        }
+
+        let podman = self.podman.clone();
+        let accepting = self.accepting.clone();
+        let span = Span::current();
+        let res = tokio::task::spawn_blocking(move || {
+            let _enter = span.enter();
+
+            // If we're shut down while waiting for a pod, get_pod fails
+            let mut pod = match podman.get_pod(&language) {
+                Ok(pod) => pod,
+                Err(_) if !accepting.load(Ordering::SeqCst) => return Err(RESTARTING_MSG.into()),
+                Err(err) => return Err(err),
+            };
+            span.record("container", &pod.id());
+            let res = pod.execute_with_timeout(&language, &content, &input, timeout);
+            podman.recycle(pod);
+            res
+        })
+        .await;
+
+        match (res, cached) {
+            (Ok(Ok(output)), Some((cache, key))) => {
+                Ok(cache.put(key, output, time::Instant::now()))
+            }
+            (Ok(res), _) => res,
+            (Err(err), _) => Err(format!("Execution task failed: {}", err)),
+        }
+    }
+
+    /// Stop taking new jobs, fail jobs which are waiting for a pod,
+    /// and give running jobs until the deadline to finish before killing their pods.
+    pub async fn shutdown(&self, timeout: time::Duration) {
+        self.accepting.store(false, Ordering::SeqCst);
+        self.podman.shutdown();
+
+        let jobs = self.jobs.load(Ordering::SeqCst);
+        info!(jobs, "Shutting down, waiting for jobs in flight");
+        if self.wait_idle(timeout).await {
+            return;
+        }
+
+        warn!(
+            jobs = self.jobs.load(Ordering::SeqCst),
+            "Jobs still running after the shutdown deadline, killing pods"
+        );
+        self.podman.kill_all();
+
+        // Give the killed jobs a moment to send their replies
+        self.wait_idle(time::Duration::from_secs(5)).await;
+    }
+
+    async fn wait_idle(&self, timeout: time::Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.jobs.load(Ordering::SeqCst) > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+        }
+
+        true
+    }
+}
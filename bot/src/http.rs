@@ -0,0 +1,171 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::api::{self, ApiKeys};
+use crate::executor::Executor;
+use crate::playground;
+use crate::podmanager::PodManager;
+use crate::store::{self, ResultStore};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub podman: Arc<PodManager>,
+    pub connected: Arc<AtomicBool>,
+    /// Enables the /admin endpoints, which need an "Authorization: Bearer <token>" header
+    pub admin_token: Option<String>,
+    pub executor: Arc<Executor>,
+    /// Enables the /v1 API, see `api`
+    pub api_keys: Option<Arc<ApiKeys>>,
+    /// Enables the /results pages, see `store`
+    pub results: Option<Arc<ResultStore>>,
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<serde_json::Value>) {
+    let connected = state.connected.load(Ordering::SeqCst);
+
+    let podman = state.podman.clone();
+    let podman_res = match tokio::task::spawn_blocking(move || podman.ping()).await {
+        Ok(res) => res,
+        Err(err) => Err(format!("Ping task failed: {}", err)),
+    };
+
+    let warm_pods = state.podman.warm_pods();
+    let disabled: serde_json::Map<String, serde_json::Value> = state
+        .podman
+        .languages()
+        .disabled()
+        .into_iter()
+        .map(|(lang, reason)| (lang, reason.into()))
+        .collect();
+
+    let ready = connected && podman_res.is_ok() && warm_pods > 0;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = json!({
+        "ready": ready,
+        "gateway_connected": connected,
+        "podman": match &podman_res {
+            Ok(()) => "ok".to_string(),
+            Err(err) => err.clone(),
+        },
+        "warm_pods": warm_pods,
+        "disabled_languages": disabled,
+    });
+
+    (status, Json(body))
+}
+
+#[derive(Deserialize)]
+struct SwitchImage {
+    from: String,
+    to: String,
+}
+
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let token = match &state.admin_token {
+        Some(token) => token,
+        None => return false,
+    };
+
+    match headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(given) => tokens_match(given, token),
+        None => false,
+    }
+}
+
+/// Compare the digests of the tokens byte by byte without stopping early, so that
+/// how long it takes doesn't tell how much of `given` is right.
+fn tokens_match(given: &str, token: &str) -> bool {
+    let (given, token) = (Sha256::digest(given), Sha256::digest(token));
+    given.iter().zip(token.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Replace the image `from` with `to`, or restart it from a rebuilt image if they're the same.
+/// Returns once the new image has passed its self-test and requests go to it.
+async fn switch_image(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SwitchImage>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, Json(json!({"error": "Unauthorized"})));
+    }
+
+    info!(from = %req.from, to = %req.to, "Switching image");
+    let podman = state.podman.clone();
+    let res = tokio::task::spawn_blocking(move || podman.switch_image(&req.from, &req.to)).await;
+    match res {
+        Ok(Ok(())) => (StatusCode::OK, Json(json!({"ok": true}))),
+        Ok(Err(err)) => {
+            warn!(error = %err, "Switching image failed");
+            (StatusCode::CONFLICT, Json(json!({"error": err})))
+        }
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": format!("Switch task failed: {}", err)})),
+        ),
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+
+    let router = match state.admin_token {
+        Some(_) => router.route("/admin/images", post(switch_image)),
+        None => router,
+    };
+
+    let router = match &state.api_keys {
+        Some(keys) if keys.has_playground() => {
+            router.merge(api::routes()).merge(playground::routes())
+        }
+        Some(_) => router.merge(api::routes()),
+        None => router,
+    };
+
+    let router = match state.results {
+        Some(_) => router.merge(store::routes()),
+        None => router,
+    };
+
+    router.with_state(state)
+}
+
+pub async fn serve(addr: SocketAddr, state: AppState) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(%addr, error = %err, "Couldn't bind HTTP server");
+            return;
+        }
+    };
+
+    info!(%addr, "HTTP server listening");
+    if let Err(err) = axum::serve(listener, router(state)).await {
+        error!(error = %err, "HTTP server error");
+    }
+}
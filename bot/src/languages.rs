@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
+
+use tracing::{info, warn};
+
+use crate::selftest::Report;
+
+/// Keeps track of which languages are disabled because they failed their self-test,
+/// so that we can tell users instead of failing their requests in some odd way.
+#[derive(Default)]
+pub struct Registry {
+    disabled: RwLock<HashMap<String, String>>,
+}
+
+impl Registry {
+    /// The names of every language in `../langs`, sorted.
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = match fs::read_dir("../langs") {
+            Ok(paths) => paths
+                .flatten()
+                .map(|path| path.file_name().to_string_lossy().to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
+    /// Why `language` is disabled, if it is.
+    pub fn why_disabled(&self, language: &str) -> Option<String> {
+        self.disabled.read().unwrap().get(language).cloned()
+    }
+
+    /// The disabled languages and why, sorted by language.
+    pub fn disabled(&self) -> Vec<(String, String)> {
+        let mut disabled: Vec<(String, String)> = self
+            .disabled
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(lang, reason)| (lang.clone(), reason.clone()))
+            .collect();
+        disabled.sort();
+        disabled
+    }
+
+    /// The failures in `report` of languages for which `owns` is true, and which work now.
+    pub fn regressions(&self, report: &Report, owns: impl Fn(&str) -> bool) -> Vec<(String, String)> {
+        let disabled = self.disabled.read().unwrap();
+        report
+            .failed
+            .iter()
+            .filter(|(lang, _)| owns(lang) && !disabled.contains_key(lang))
+            .cloned()
+            .collect()
+    }
+
+    /// Disable the languages which failed in `report`, and enable those which passed,
+    /// ignoring the languages for which `owns` is false.
+    pub fn record(&self, report: &Report, owns: impl Fn(&str) -> bool) {
+        let mut disabled = self.disabled.write().unwrap();
+        for lang in report.passed.iter().filter(|lang| owns(lang)) {
+            if disabled.remove(lang).is_some() {
+                info!(language = %lang, "Enabling language, it passed its self-test");
+            }
+        }
+        for (lang, reason) in report.failed.iter().filter(|(lang, _)| owns(lang)) {
+            if disabled.insert(lang.clone(), reason.clone()).is_none() {
+                warn!(language = %lang, reason = %reason, "Disabling language, it failed its self-test");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_self_tests() {
+        let registry = Registry::default();
+        let owns = |lang: &str| lang != "lean";
+        registry.record(&Report::parse("ok c\nfail ruby exit code 1\nfail lean wrong output\n"), owns);
+        assert_eq!(
+            registry.disabled(),
+            vec![("ruby".to_string(), "exit code 1".to_string())]
+        );
+
+        // ruby is already broken, so only python counts against a new image
+        let report = Report::parse("fail python wrong output\nfail ruby wrong output\n");
+        assert_eq!(
+            registry.regressions(&report, owns),
+            vec![("python".to_string(), "wrong output".to_string())]
+        );
+
+        registry.record(&Report::parse("ok ruby\n"), owns);
+        assert_eq!(registry.why_disabled("ruby"), None);
+    }
+}
@@ -0,0 +1,18 @@
+pub mod api;
+pub mod backend;
+pub mod build;
+pub mod cache;
+pub mod discord;
+pub mod executor;
+pub mod http;
+pub mod languages;
+pub mod lint;
+pub mod manifest;
+pub mod parse;
+pub mod playground;
+pub mod podmanager;
+pub mod routes;
+pub mod scaffold;
+pub mod selftest;
+pub mod store;
+pub mod testsuite;
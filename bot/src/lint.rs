@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::manifest::Manifest;
+use crate::testsuite;
+
+lazy_static! {
+    static ref GIT_CLONE_RX: Regex = Regex::new(r"\bgit\b.*\bclone\b").unwrap();
+    static ref GIT_CHECKOUT_SHA_RX: Regex =
+        Regex::new(r"\bgit\b.*\bcheckout\b.*\b[0-9a-f]{40}\b").unwrap();
+    static ref DOWNLOAD_RX: Regex = Regex::new(r"\b(curl|wget)\b").unwrap();
+    static ref CHECKSUM_RX: Regex =
+        Regex::new(r"\b(sha(1|224|256|384|512)sum|shasum|b2sum)\b|\bgpg\b.*--verify|--checksum")
+            .unwrap();
+    static ref DONE_RX: Regex = Regex::new(r"\btouch\b.*DEPLOYDIR\}?/\.done").unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+fn error(message: impl Into<String>) -> Problem {
+    Problem {
+        severity: Severity::Error,
+        message: message.into(),
+    }
+}
+
+fn warning(message: impl Into<String>) -> Problem {
+    Problem {
+        severity: Severity::Warning,
+        message: message.into(),
+    }
+}
+
+/// Check a `compile.sh` against the rules in the README.
+pub fn lint_compile_script(text: &str) -> Vec<Problem> {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .collect();
+    let count = |rx: &Regex| lines.iter().filter(|line| rx.is_match(line)).count();
+
+    let mut problems = Vec::new();
+    let clones = count(&GIT_CLONE_RX);
+    let pinned = count(&GIT_CHECKOUT_SHA_RX);
+    if clones > pinned {
+        problems.push(error(format!(
+            "compile.sh: {} git clone(s), but only {} git checkout(s) of a commit hash",
+            clones, pinned
+        )));
+    }
+
+    if count(&DOWNLOAD_RX) > 0 && count(&CHECKSUM_RX) == 0 {
+        problems.push(error("compile.sh: downloads with curl or wget without verifying a checksum"));
+    }
+
+    if count(&DONE_RX) == 0 {
+        problems.push(error("compile.sh: doesn't touch \"$DEPLOYDIR/.done\""));
+    }
+
+    problems
+}
+
+/// Check a language's directory. `exists` tells whether another language exists.
+pub fn lint_language(dir: &Path, exists: impl Fn(&str) -> bool) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if !dir.join("run.sh").is_file() {
+        problems.push(error("Missing run.sh"));
+    }
+
+    if dir.join("compile.sh").exists() {
+        match fs::read_to_string(dir.join("compile.sh")) {
+            Ok(text) => problems.extend(lint_compile_script(&text)),
+            Err(err) => problems.push(error(format!("Reading compile.sh failed: {}", err))),
+        }
+    }
+
+    if !testsuite::hello_world_names().iter().any(|name| dir.join(name).is_file()) {
+        // Some languages only exist for some machines, so this isn't an error
+        problems.push(warning(format!(
+            "No hello world example for this machine, one of {}",
+            testsuite::hello_world_names().join(", ")
+        )));
+    }
+
+    match Manifest::load(dir) {
+        Ok(manifest) => {
+            for dep in manifest.depends.iter().filter(|dep| !exists(dep)) {
+                problems.push(error(format!("language.toml: depends on {}, which doesn't exist", dep)));
+            }
+        }
+        Err(err) => problems.push(error(err)),
+    }
+
+    problems
+}
+
+/// Check every language in `langs_dir`. Returns the problems along with the language's name.
+pub fn lint_all(langs_dir: &Path) -> Result<Vec<(String, Problem)>, String> {
+    let paths = fs::read_dir(langs_dir)
+        .map_err(|err| format!("Reading {} failed: {}", langs_dir.display(), err))?;
+    let mut names: Vec<String> = paths
+        .flatten()
+        .filter(|path| path.path().is_dir())
+        .map(|path| path.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+
+    let mut problems = Vec::new();
+    for name in &names {
+        let exists = |dep: &str| names.iter().any(|name| name == dep);
+        for problem in lint_language(&langs_dir.join(name), exists) {
+            problems.push((name.clone(), problem));
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lints_compile_scripts() {
+        let good = "git clone https://example.com/x.git\ncd x\n\
+            git checkout 0af9b7ad6f4145a88919d61d39f9277ed5db2070\n\
+            curl -LO https://example.com/y.tgz\necho 'abc  y.tgz' | sha256sum -c\n\
+            touch \"$DEPLOYDIR/.done\"\n";
+        assert_eq!(lint_compile_script(good), Vec::new());
+
+        let bad = "git clone https://example.com/x.git\ngit checkout main\n\
+            # sha256sum in a comment doesn't count\nwget https://example.com/y.tgz\n";
+        let messages: Vec<String> = lint_compile_script(bad).into_iter().map(|p| p.message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "compile.sh: 1 git clone(s), but only 0 git checkout(s) of a commit hash",
+                "compile.sh: downloads with curl or wget without verifying a checksum",
+                "compile.sh: doesn't touch \"$DEPLOYDIR/.done\"",
+            ]
+        );
+    }
+}
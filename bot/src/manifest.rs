@@ -0,0 +1,59 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// A language's manifest, `langs/<lang>/language.toml`. Every part of it is optional.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Languages which have to be built before this one, see `langbot-build`
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Whether running the same program always gives the same result,
+    /// so that the bot may reuse results instead of running the program again
+    #[serde(default)]
+    pub deterministic: bool,
+    pub conformance: Option<Conformance>,
+}
+
+/// How to do a few basic things in the language, for the generated conformance tests.
+/// Each snippet is one or more statements.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conformance {
+    /// The program the snippets go in, with `{body}` where they go
+    #[serde(default = "default_program")]
+    pub program: String,
+    /// Print `{text}` and a newline to stdout
+    pub print: String,
+    /// Print `{text}` and a newline to stderr
+    pub eprint: String,
+    /// Exit with the exit code `{code}`
+    pub exit: String,
+    /// Write `{text}` to the file `{name}` in the current directory
+    pub write_file: String,
+    /// Copy all of stdin to stdout
+    pub copy_stdin: String,
+}
+
+fn default_program() -> String {
+    "{body}".to_string()
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|err| format!("Invalid manifest: {}", err))
+    }
+
+    /// Read `language.toml` in the language's directory, if there is one.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join("language.toml");
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Reading {} failed: {}", path.display(), err)),
+        }
+    }
+}
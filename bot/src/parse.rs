@@ -0,0 +1,124 @@
+//! Finding code to run in messages.
+
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+
+lazy_static! {
+    static ref MULTILINE_CODE_RX: Regex = {
+        let pattern = r"!([a-zA-Z][a-zA-Z0-9+_]*)\s+```(?:\S+\r?\n)?\s*(.*?)```";
+        RegexBuilder::new(pattern)
+            .dot_matches_new_line(true)
+            .build()
+            .unwrap()
+    };
+    static ref INLINE_CODE_RX: Regex = {
+        let pattern = r"!([a-zA-Z][a-zA-Z0-9+_]*)\s+`(.*?)`";
+        Regex::new(pattern).unwrap()
+    };
+}
+
+/// A request to run some code, like "!python `print(1)`", or a language name
+/// followed by a code block. The fence's language tag, if any, is ignored.
+#[derive(Debug, PartialEq)]
+pub struct ParsedRequest {
+    /// Lowercased
+    pub language: String,
+    pub code: String,
+}
+
+impl ParsedRequest {
+    /// Find the first code block in `text`, or the first inline code if there are no code blocks.
+    pub fn parse(text: &str) -> Option<Self> {
+        let caps = MULTILINE_CODE_RX
+            .captures(text)
+            .or_else(|| INLINE_CODE_RX.captures(text))?;
+
+        Some(Self {
+            language: caps.get(1).unwrap().as_str().to_lowercase(),
+            code: caps.get(2).unwrap().as_str().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn parse(text: &str) -> Option<(String, String)> {
+        ParsedRequest::parse(text).map(|req| (req.language, req.code))
+    }
+
+    fn req(language: &str, code: &str) -> Option<(String, String)> {
+        Some((language.to_string(), code.to_string()))
+    }
+
+    #[test]
+    fn parses_inline_code() {
+        assert_eq!(parse("!python `print(1)`"), req("python", "print(1)"));
+        assert_eq!(parse("look: !Python\t`print(1)` cool"), req("python", "print(1)"));
+        assert_eq!(parse("!c++ `int main() {}`"), req("c++", "int main() {}"));
+        assert_eq!(parse("!x86_64 `ret`"), req("x86_64", "ret"));
+        assert_eq!(parse("!py ``"), req("py", ""));
+
+        // Inline code ends at the first backtick, and doesn't span lines
+        assert_eq!(parse("!sh `echo `date``"), req("sh", "echo "));
+        assert_eq!(parse("!sh `echo\nhi`"), None);
+
+        assert_eq!(parse("!python print(1)"), None);
+        assert_eq!(parse("! python `print(1)`"), None);
+        assert_eq!(parse("!1python `print(1)`"), None);
+        assert_eq!(parse("!python`print(1)`"), None);
+        assert_eq!(parse("python `print(1)`"), None);
+    }
+
+    #[test]
+    fn parses_code_blocks() {
+        assert_eq!(parse("!python\n```\nprint(1)\n```"), req("python", "print(1)\n"));
+        assert_eq!(parse("!python ```print(1)```"), req("python", "print(1)"));
+        assert_eq!(parse("!python\n```py\nprint(1)\n```"), req("python", "print(1)\n"));
+        assert_eq!(parse("!cpp\n```c++\nint x;\n```"), req("cpp", "int x;\n"));
+        assert_eq!(parse("!python\r\n```py\r\nprint(1)\r\n```"), req("python", "print(1)\r\n"));
+        assert_eq!(parse("!python\n```\n\n  if x:\n    y\n```"), req("python", "if x:\n    y\n"));
+
+        // Inline backticks in a code block are part of the code
+        assert_eq!(parse("!sh\n```\necho `date`\n```"), req("sh", "echo `date`\n"));
+
+        // Code blocks win over inline code, wherever they are
+        assert_eq!(parse("!c `1` !sh\n```\n2\n```"), req("sh", "2\n"));
+
+        // A block which isn't closed is inline code with a stray backtick
+        assert_eq!(parse("!sh ```echo"), req("sh", ""));
+    }
+
+    proptest! {
+        #[test]
+        fn inline_code_round_trips(
+            language in "[a-zA-Z][a-zA-Z0-9+_]{0,10}",
+            code in "[^`\r\n]*",
+        ) {
+            let text = format!("!{} `{}`", language, code);
+            prop_assert_eq!(parse(&text), req(&language.to_lowercase(), &code));
+        }
+
+        #[test]
+        fn code_blocks_round_trip(
+            language in "[a-zA-Z][a-zA-Z0-9+_]{0,10}",
+            tag in "[a-z+]{1,8}",
+            newline in prop_oneof!["\n", "\r\n"],
+            code in "[^`\\s][^`]*",
+        ) {
+            let text = format!("!{}\n```{}{}{}```", language, tag, newline, code);
+            prop_assert_eq!(parse(&text), req(&language.to_lowercase(), &code));
+        }
+
+        #[test]
+        fn found_code_is_in_the_message(text in "(!?[a-z]{1,3}\\s*`{0,3}[^!`]{0,5}){0,6}") {
+            if let Some(req) = ParsedRequest::parse(&text) {
+                prop_assert!(text.contains(&req.code));
+                let command = format!("!{}", req.language);
+                prop_assert!(text.to_lowercase().contains(&command));
+            }
+        }
+    }
+}
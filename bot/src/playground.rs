@@ -0,0 +1,18 @@
+//! A web page for trying languages outside of Discord, on top of the `/v1` API.
+
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+
+use crate::http::AppState;
+
+/// The whole playground, styles and script included.
+const PAGE: &str = include_str!("../assets/playground.html");
+
+async fn page() -> Html<&'static str> {
+    Html(PAGE)
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(page))
+}
@@ -1,17 +1,92 @@
-use std::io::Write;
-use std::process::{Command, ExitStatus, Stdio};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread;
 use std::time;
-use std::io::Cursor;
-use tar;
+use std::io::{Cursor, Read};
+use tracing::{debug, info, warn};
+
+use crate::backend::Backend;
+use crate::languages::Registry;
+use crate::routes::Router;
+use crate::selftest;
 
 type Archive = tar::Archive<Cursor<Vec<u8>>>;
 
+/// The containers this instance created and hasn't killed yet.
+#[derive(Default)]
+struct Live {
+    ids: HashSet<String>,
+    /// How many containers are being created or restored right now. The backend might
+    /// already list them, but they aren't in `ids` yet.
+    creating: usize,
+}
+
+type LiveSet = Arc<Mutex<Live>>;
+
+/// Every container we create gets this label, with the ID of the instance which created it.
+pub const INSTANCE_LABEL: &str = "langbot.instance";
+
+/// Containers created with `Pod::create` get this label instead, with the PID of the process
+/// which created them, so that a running bot doesn't reap them.
+pub const STANDALONE_LABEL: &str = "langbot.standalone";
+
+/// How long programs get to run.
+pub const EXEC_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+
+/// How many reset pods to keep around at most, on top of the one fresh pod.
+const MAX_IDLE_PODS: usize = 4;
+
+/// Requests for a language within this window decide how many warm pods it gets.
+const RATE_WINDOW: time::Duration = time::Duration::from_secs(10 * 60);
+
+/// A language gets one more warm pod for every this many requests in the window.
+const REQUESTS_PER_POD: usize = 20;
+
 pub struct Pod {
     id: String,
+    /// The image the container was created from
+    tag: String,
+    /// The generation of the image, see `Routing`
+    generation: u64,
+    backend: Arc<dyn Backend>,
     killed: Arc<Mutex<bool>>,
+    live: LiveSet,
+    /// Number of executions so far
+    uses: u32,
+    /// Set when something went wrong, so that we don't trust the container to be reset
+    tainted: bool,
+    /// The language which last ran in the container, whose files are probably cached
+    language: Option<String>,
+}
+
+/// Stops the killer thread when dropped, and waits for it, so that a container
+/// which is going to be reused can't be killed by a watchdog from an earlier execution.
+struct Watchdog {
+    done_ch: Option<mpsc::Sender<()>>,
+    killer: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.done_ch.take());
+        if let Some(killer) = self.killer.take() {
+            let _ = killer.join();
+        }
+    }
+}
+
+/// What a program gets besides its source.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Input {
+    pub stdin: String,
+    pub args: Vec<String>,
 }
 
 pub struct ExecResult {
@@ -19,62 +94,139 @@ pub struct ExecResult {
     pub stderr: Option<String>,
     pub status: ExitStatus,
     pub files: Option<Arc<Mutex<Archive>>>,
+    /// The container was killed because the program ran for too long
+    pub timed_out: bool,
+    /// The result came from the executor's cache instead of a container
+    pub cached: bool,
 }
 
-impl Pod {
-    fn new_from_tag(tag: &str) -> Result<Pod, String> {
-        let output = Command::new("podman")
-            .arg("container")
-            .arg("create")
-            .arg("--rm")
-            .arg("--network=none")
-            .arg(tag)
-            .arg("tail")
-            .arg("-f")
-            .arg("/dev/null")
-            .stderr(Stdio::inherit())
-            .output();
-        let output = match output {
-            Ok(output) => output,
-            Err(err) => return Err(format!("Creating container failed: {}", err)),
+/// A file a program created.
+pub struct OutputFile {
+    /// Relative to the program's working directory
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+impl ExecResult {
+    /// Read the non-empty files from `files`. This can only be done once.
+    pub fn read_files(&self) -> Result<Vec<OutputFile>, String> {
+        let mut files = Vec::new();
+        let mut archive = match &self.files {
+            Some(archive) => archive.lock().unwrap(),
+            None => return Ok(files),
         };
 
-        if !output.status.success() {
-            return Err("Creating cointainer failed".into());
+        let entries = archive.entries().map_err(|err| format!("Reading files failed: {}", err))?;
+        for ent in entries {
+            let mut ent = ent.map_err(|err| format!("Reading files failed: {}", err))?;
+            if ent.size() == 0 {
+                continue;
+            }
+
+            let name = match ent.path() {
+                Ok(path) => path.to_string_lossy().to_string(),
+                Err(err) => return Err(format!("Invalid file name: {}", err)),
+            };
+            let mut content = Vec::new();
+            ent.read_to_end(&mut content)
+                .map_err(|err| format!("Reading {} failed: {}", name, err))?;
+            files.push(OutputFile { name, content });
         }
 
-        let id = match String::from_utf8(output.stdout) {
-            Ok(id) => id.trim().to_string(),
-            Err(err) => return Err(format!("Podman retuned invalid UTF-8: {}", err)),
+        Ok(files)
+    }
+}
+
+impl Pod {
+    fn new_from_tag(
+        backend: Arc<dyn Backend>,
+        tag: &str,
+        generation: u64,
+        label: (&str, &str),
+        live: LiveSet,
+        snapshot: bool,
+    ) -> Result<Pod, String> {
+        let id = match register(&live, || backend.create(tag, &[label])) {
+            Ok(id) => id,
+            Err(err) => {
+                warn!(error = %err, "Creating container failed");
+                return Err(err);
+            }
         };
 
-        let output = Command::new("podman")
-            .arg("container")
-            .arg("start")
-            .arg(&id)
-            .stderr(Stdio::inherit())
-            .output();
-        let output = match output {
-            Ok(output) => output,
-            Err(err) => return Err(format!("Creating container failed: {}", err)),
+        debug!(container = %id, tag, "Created container");
+        let mut pod = Pod {
+            id,
+            tag: tag.to_string(),
+            generation,
+            backend,
+            killed: Arc::new(Mutex::new(false)),
+            live,
+            uses: 0,
+            tainted: false,
+            language: None,
         };
 
-        if !output.status.success() {
-            return Err("Starting container failed".into());
+        if snapshot {
+            if let Err(err) = pod.backend.snapshot(&pod.id) {
+                warn!(container = %pod.id, error = %err, "Snapshotting container failed, it won't be reused");
+                pod.tainted = true;
+            }
         }
 
-        Ok(Pod { id, killed: Arc::new(Mutex::new(false)) })
+        Ok(pod)
     }
 
-    pub fn execute(&mut self, language: &str, content: &str) -> Result<ExecResult, String> {
+    /// Restore a container from a checkpoint made by `make_checkpoint`.
+    fn new_from_checkpoint(
+        backend: Arc<dyn Backend>,
+        tag: &str,
+        generation: u64,
+        path: &Path,
+        live: LiveSet,
+    ) -> Result<Pod, String> {
+        let id = register(&live, || backend.restore(path))?;
+
+        debug!(container = %id, "Restored container");
+        Ok(Pod {
+            id,
+            tag: tag.to_string(),
+            generation,
+            backend,
+            killed: Arc::new(Mutex::new(false)),
+            live,
+            uses: 0,
+            // Restored containers have no snapshot of their own to be reset to,
+            // and resetting would kill the prewarmed runtime anyway, so they're never reused,
+            // whatever LANGBOT_POD_REUSE says
+            tainted: true,
+            language: None,
+        })
+    }
+
+    /// Create a pod from the image `tag` which isn't managed by a pod manager,
+    /// for tools which run code the same way the bot does.
+    pub fn create(backend: Arc<dyn Backend>, tag: &str) -> Result<Pod, String> {
+        let pid = std::process::id().to_string();
+        let live = Arc::new(Mutex::new(Live::default()));
+        Self::new_from_tag(backend, tag, 0, (STANDALONE_LABEL, &pid), live, false)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Kill the container unless the returned watchdog is dropped before `timeout`.
+    fn watchdog(&self, timeout: time::Duration) -> Watchdog {
+        let (done_send, done_recv) = mpsc::channel::<()>();
         let killed_th = self.killed.clone();
         let id_th = self.id.clone();
-        thread::spawn(move || {
-            for _ in 0..3 {
-                thread::sleep(time::Duration::from_millis(1000));
-                if *killed_th.lock().unwrap() {
-                    break;
-                }
+        let backend_th = self.backend.clone();
+        let span = tracing::Span::current();
+        let killer = thread::spawn(move || {
+            let _enter = span.enter();
+            if let Err(mpsc::RecvTimeoutError::Disconnected) = done_recv.recv_timeout(timeout) {
+                return;
             }
 
             let mut killed_lock = killed_th.lock().unwrap();
@@ -82,54 +234,79 @@ impl Pod {
                 return;
             }
 
-            let output = Command::new("podman")
-                .arg("container")
-                .arg("kill")
-                .arg(&id_th)
-                .stderr(Stdio::inherit())
-                .output();
-            match output {
-                Ok(_) => (),
-                Err(err) => {
-                    eprintln!("Killing container {} failed: {}", id_th, err);
-                    return;
-                }
+            info!("Execution timed out, killing container");
+            if let Err(err) = backend_th.kill(&id_th) {
+                warn!(error = %err, "Killing container failed");
+                return;
             }
 
             *killed_lock = true;
         });
 
-        let child = Command::new("podman")
-            .arg("exec")
-            .arg("-i")
-            .arg(&self.id)
-            .arg("./scripts/run.sh")
-            .arg(language)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn();
-        let mut child = match child {
-            Ok(child) => child,
-            Err(err) => return Err(format!("Running program failed: {}", err)),
-        };
+        Watchdog {
+            done_ch: Some(done_send),
+            killer: Some(killer),
+        }
+    }
 
-        if let Some(stdin) = &mut child.stdin {
-            match stdin.write_all(&content.as_bytes()) {
-                Ok(()) => (),
-                Err(err) => return Err(format!("Running program failed: {}", err)),
-            }
+    pub fn execute(&mut self, language: &str, content: &str) -> Result<ExecResult, String> {
+        self.execute_with_timeout(language, content, &Input::default(), EXEC_TIMEOUT)
+    }
+
+    /// Like `execute`, but the program gets `input`,
+    /// and the container is killed after `timeout` instead of `EXEC_TIMEOUT`.
+    #[tracing::instrument(name = "execute", skip(self, content, input), fields(container = %self.id))]
+    pub fn execute_with_timeout(
+        &mut self,
+        language: &str,
+        content: &str,
+        input: &Input,
+        timeout: time::Duration,
+    ) -> Result<ExecResult, String> {
+        self.uses += 1;
+        self.language = Some(language.to_string());
+
+        let watchdog = self.watchdog(timeout);
+        let res = self.run_program(language, content, input);
+        drop(watchdog);
+
+        if res.is_err() {
+            self.tainted = true;
         }
+        res
+    }
 
-        let output = match child.wait_with_output() {
+    fn run_program(
+        &mut self,
+        language: &str,
+        content: &str,
+        input: &Input,
+    ) -> Result<ExecResult, String> {
+        // scripts/run.sh takes the size of the program's stdin, which comes before the source
+        let mut args = vec![input.stdin.len().to_string()];
+        args.extend(input.args.iter().cloned());
+        let stdin = [input.stdin.as_bytes(), content.as_bytes()].concat();
+        let output = match self.backend.run(&self.id, language, &args, &stdin) {
             Ok(output) => output,
-            Err(err) => return Err(format!("Running program failed: {}", err)),
+            // Killing the container may break the backend's connection to it
+            Err(err) if *self.killed.lock().unwrap() => {
+                info!(error = %err, "Execution timed out");
+                return Ok(ExecResult {
+                    stdout: None,
+                    stderr: None,
+                    status: ExitStatus::from_raw(137 << 8),
+                    files: None,
+                    timed_out: true,
+                    cached: false,
+                });
+            }
+            Err(err) => return Err(err),
         };
 
         let mut errmsg: Option<String> = None;
         {
             let msg = String::from_utf8_lossy(&output.stderr).trim_end().to_string();
-            if msg.len() > 0 {
+            if !msg.is_empty() {
                 errmsg = Some(msg);
             }
         }
@@ -137,11 +314,13 @@ impl Pod {
         let mut outmsg: Option<String> = None;
         {
             let msg = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
-            if msg.len() > 0 {
+            if !msg.is_empty() {
                 outmsg = Some(msg);
             }
         }
 
+        info!(status = %output.status, "Execution finished");
+
         let killed_lock = self.killed.lock().unwrap();
         if *killed_lock {
             return Ok(ExecResult {
@@ -149,33 +328,25 @@ impl Pod {
                 stderr: errmsg,
                 status: output.status,
                 files: None,
+                timed_out: true,
+                cached: false,
             })
         }
-
-        let child = Command::new("podman")
-            .arg("exec")
-            .arg("-i")
-            .arg(&self.id)
-            .arg("./scripts/get-files.sh")
-            .arg(language)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn();
-        let child = match child {
-            Ok(child) => child,
-            Err(err) => return Err(format!("Getting files failed: {}", err)),
-        };
-
         drop(killed_lock);
 
-        let tar_output = match child.wait_with_output() {
-            Ok(output) => output,
-            Err(err) => return Err(format!("Getting files failed: {}", err)),
+        // If the container gets killed while we're getting files, that's not an error,
+        // we just don't get any files
+        let tar = match self.backend.files(&self.id, language) {
+            Ok(tar) => tar,
+            Err(err) => {
+                warn!(error = %err, "Getting files failed");
+                self.tainted = true;
+                Vec::new()
+            }
         };
 
-        let files = if tar_output.stdout.len() > 0 && tar_output.status.success() {
-            Some(Arc::new(Mutex::new(Archive::new(Cursor::new(tar_output.stdout)))))
+        let files = if !tar.is_empty() {
+            Some(Arc::new(Mutex::new(Archive::new(Cursor::new(tar)))))
         } else {
             None
         };
@@ -185,87 +356,1298 @@ impl Pod {
             stderr: errmsg,
             status: output.status,
             files,
+            timed_out: false,
+            cached: false,
         })
     }
 }
 
+impl Pod {
+    fn reusable(&self, max_uses: u32) -> bool {
+        !self.tainted && self.uses < max_uses && !*self.killed.lock().unwrap()
+    }
+
+    /// Run the language's hello world example, see `Backend::prime`.
+    fn prime(&mut self, language: &str) -> Result<(), String> {
+        let watchdog = self.watchdog(time::Duration::from_secs(30));
+        let res = self.backend.prime(&self.id, language);
+        drop(watchdog);
+
+        if *self.killed.lock().unwrap() {
+            return Err("Priming timed out".into());
+        }
+        res?;
+        self.language = Some(language.to_string());
+        Ok(())
+    }
+
+    /// Run the self-test, see `Backend::self_test`.
+    fn self_test(&mut self, languages: &[String]) -> Result<selftest::Report, String> {
+        let watchdog = self.watchdog(time::Duration::from_secs(15 * 60));
+        let res = self.backend.self_test(&self.id, languages);
+        drop(watchdog);
+
+        if *self.killed.lock().unwrap() {
+            return Err("Self-test timed out".into());
+        }
+        Ok(selftest::Report::parse(&res?))
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        if *self.killed.lock().unwrap() {
+            return Err("The container was killed".into());
+        }
+
+        self.backend.reset(&self.id)
+    }
+}
+
 impl Drop for Pod {
     fn drop(&mut self) {
         let mut killed_lock = self.killed.lock().unwrap();
         if !*killed_lock {
-            let output = Command::new("podman")
-                .arg("container")
-                .arg("kill")
-                .arg(&self.id)
-                .stderr(Stdio::inherit())
-                .output();
-            match output {
-                Ok(_) => (),
-                Err(err) => {
-                    eprintln!("Killing container {} failed: {}", self.id, err);
-                    return;
-                }
+            match self.backend.kill(&self.id) {
+                Ok(()) => *killed_lock = true,
+                Err(err) => warn!(container = %self.id, error = %err, "Killing container failed"),
             }
+        }
 
-            *killed_lock = true;
+        // If killing failed, this leaves the container to the reaper
+        self.live.lock().unwrap().ids.remove(&self.id);
+    }
+}
+
+/// Create a container with `create` and add it to `live`. The lock isn't held while
+/// the backend works, so that pods can be dropped meanwhile, but the reaper leaves
+/// our containers alone until the new one is registered.
+fn register(
+    live: &Mutex<Live>,
+    create: impl FnOnce() -> Result<String, String>,
+) -> Result<String, String> {
+    live.lock().unwrap().creating += 1;
+    let id = create();
+
+    let mut live_lock = live.lock().unwrap();
+    live_lock.creating -= 1;
+    if let Ok(id) = &id {
+        live_lock.ids.insert(id.clone());
+    }
+    id
+}
+
+/// A running bot instance, registered in `LANGBOT_STATE_DIR` with a file named after
+/// its ID, which it keeps locked for as long as it runs. The lock goes away with
+/// the process, however it dies, so instances whose file isn't locked are dead.
+struct Instance {
+    id: String,
+    dir: PathBuf,
+    _lock: fs::File,
+}
+
+impl Instance {
+    fn register(dir: &Path, id: String) -> Result<Instance, String> {
+        if id.is_empty() || id.contains('/') || id.starts_with('.') {
+            return Err(format!("Invalid instance ID: {}", id));
+        }
+        if let Err(err) = fs::create_dir_all(dir) {
+            return Err(format!("Creating {} failed: {}", dir.display(), err));
         }
+
+        let path = dir.join(format!("{}.lock", id));
+        let file = match fs::File::create(&path) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Creating {} failed: {}", path.display(), err)),
+        };
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(fs::TryLockError::WouldBlock) => {
+                return Err(format!("Instance {} is already running", id));
+            }
+            Err(fs::TryLockError::Error(err)) => {
+                return Err(format!("Locking {} failed: {}", path.display(), err));
+            }
+        }
+
+        Ok(Instance {
+            id,
+            dir: dir.to_path_buf(),
+            _lock: file,
+        })
+    }
+
+    /// The IDs of the other instances which registered in our directory, but are dead,
+    /// with their files, which are locked until they're dropped.
+    fn dead(&self) -> Result<Vec<(String, PathBuf, fs::File)>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => return Err(format!("Listing {} failed: {}", self.dir.display(), err)),
+        };
+
+        let mut dead = Vec::new();
+        for ent in entries.flatten() {
+            let name = ent.file_name().to_string_lossy().to_string();
+            let id = match name.strip_suffix(".lock") {
+                Some(id) if id != self.id => id.to_string(),
+                _ => continue,
+            };
+            // The file might be gone already if another instance reaped it first
+            let file = match fs::File::open(ent.path()) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            if file.try_lock().is_ok() {
+                dead.push((id, ent.path(), file));
+            }
+        }
+        Ok(dead)
+    }
+}
+
+/// Find containers left behind by a crashed or killed bot, and remove them: containers
+/// labelled with our own ID which aren't live pods (in case the ID was set with
+/// `LANGBOT_INSTANCE` and we're restarting), and containers of dead instances,
+/// see `Instance`. Containers of instances which are running, or which never registered
+/// in our directory, are left alone.
+fn reap(backend: &dyn Backend, instance: &Instance, live: &Mutex<Live>) -> Result<usize, String> {
+    let ours = backend.list(&format!("{}={}", INSTANCE_LABEL, instance.id))?;
+    let live_lock = live.lock().unwrap();
+    let mut stale: Vec<String> = if live_lock.creating > 0 {
+        // The list might contain a container which isn't registered yet
+        debug!("Containers are being created, not reaping our own this time");
+        Vec::new()
+    } else {
+        ours.into_iter().filter(|id| !live_lock.ids.contains(id)).collect()
+    };
+    drop(live_lock);
+
+    let dead = instance.dead()?;
+    for (id, _, _) in &dead {
+        stale.extend(backend.list(&format!("{}={}", INSTANCE_LABEL, id))?);
+    }
+
+    if !stale.is_empty() {
+        backend.remove(&stale)?;
+    }
+    // Remove the files while they're still locked, so no one else reaps them again
+    for (id, path, _lock) in dead {
+        debug!(instance = %id, "Reaped dead instance");
+        let _ = fs::remove_file(path);
+    }
+    Ok(stale.len())
+}
+
+fn reap_and_log(backend: &dyn Backend, instance: &Instance, live: &Mutex<Live>) {
+    match reap(backend, instance, live) {
+        Ok(0) => debug!("No orphaned containers"),
+        Ok(count) => info!(count, "Reaped {} orphaned containers", count),
+        Err(err) => warn!(error = %err, "Reaping orphaned containers failed"),
+    }
+}
+
+/// Reap every `interval` until `stop_ch` is closed. Owns the instance's registration,
+/// so that other instances start reaping our containers once we've shut down.
+fn reaper(
+    backend: Arc<dyn Backend>,
+    instance: Instance,
+    live: LiveSet,
+    interval: time::Duration,
+    stop_ch: mpsc::Receiver<()>,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) = stop_ch.recv_timeout(interval) {
+        reap_and_log(backend.as_ref(), &instance, &live);
     }
 }
 
+fn instance_id() -> String {
+    if let Ok(id) = env::var("LANGBOT_INSTANCE") {
+        return id;
+    }
+
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", std::process::id(), nanos)
+}
+
 enum Request {
     CreatePod,
+    Recycled(Pod),
     Terminate,
 }
 
 type Response = Result<Pod, String>;
 
-fn pod_server(tag: String, req_ch: mpsc::Receiver<Request>, resp_ch: mpsc::Sender<Response>) {
+enum CheckpointState {
+    Pending,
+    Ready {
+        path: PathBuf,
+        tag: String,
+        generation: u64,
+    },
+    Unavailable,
+}
+
+/// Per-language checkpoints of prewarmed containers, see `LANGBOT_CHECKPOINT_LANGS`.
+type Checkpoints = Arc<Mutex<HashMap<String, CheckpointState>>>;
+
+/// Create a container for `language`, prewarm it and checkpoint it into `dir`.
+fn make_checkpoint(factory: &PodFactory, language: &str, dir: &Path) -> Result<PathBuf, String> {
+    let pod = factory.create()?;
+    let image: String = factory
+        .tag
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .collect();
+    let path = dir.join(format!("{}-{}-{}.tar", language, image, factory.generation));
+    let res = factory.backend.checkpoint(&pod.id, language, &path);
+
+    // The backend removes the container either way
+    *pod.killed.lock().unwrap() = true;
+    res?;
+    Ok(path)
+}
+
+fn checkpointer(
+    factory: PodFactory,
+    routing: SharedRouting,
+    checkpoints: Checkpoints,
+    dir: PathBuf,
+    languages: Vec<String>,
+) {
+    if let Err(err) = fs::create_dir_all(&dir) {
+        warn!(error = %err, "Creating checkpoint directory failed");
+    }
+
+    for language in languages {
+        let start = time::Instant::now();
+        let (tag, generation) = routing.read().unwrap().current(&language);
+        let factory = factory.for_image(&tag, generation);
+        let state = match make_checkpoint(&factory, &language, &dir) {
+            Ok(path) => {
+                info!(language = %language, elapsed = ?start.elapsed(), "Checkpointed container");
+                CheckpointState::Ready {
+                    path,
+                    tag,
+                    generation,
+                }
+            }
+            Err(err) => {
+                warn!(
+                    language = %language,
+                    error = %err,
+                    "Checkpointing container failed, creating containers normally"
+                );
+                CheckpointState::Unavailable
+            }
+        };
+        checkpoints.lock().unwrap().insert(language, state);
+    }
+}
+
+/// Everything needed to create pods.
+#[derive(Clone)]
+struct PodFactory {
+    backend: Arc<dyn Backend>,
+    tag: String,
+    generation: u64,
+    /// The label every container gets, see INSTANCE_LABEL
+    label: (&'static str, String),
+    live: LiveSet,
+    /// Whether pods will be reset and reused
+    reuse: bool,
+    /// Whether the pod servers keep a fresh pod ready for the next request
+    spare: bool,
+}
+
+impl PodFactory {
+    fn for_image(&self, tag: &str, generation: u64) -> PodFactory {
+        PodFactory {
+            tag: tag.to_string(),
+            generation,
+            ..self.clone()
+        }
+    }
+
+    fn create(&self) -> Result<Pod, String> {
+        Pod::new_from_tag(
+            self.backend.clone(),
+            &self.tag,
+            self.generation,
+            (self.label.0, &self.label.1),
+            self.live.clone(),
+            self.reuse,
+        )
+    }
+}
+
+/// Which image each language runs in, and the current generation of each image.
+/// Replacing an image with a new build under the same tag gives it a new generation,
+/// so that pods of the old build aren't handed out any more.
+#[derive(Clone)]
+struct Routing {
+    router: Router,
+    generations: HashMap<String, u64>,
+    /// The IDs of the images, as of when they were started, if the backend has them
+    ids: HashMap<String, String>,
+}
+
+type SharedRouting = Arc<RwLock<Routing>>;
+
+impl Routing {
+    fn current(&self, language: &str) -> (String, u64) {
+        let tag = self.router.image(language);
+        (tag.to_string(), self.generations.get(tag).copied().unwrap_or(0))
+    }
+
+    fn is_current(&self, tag: &str, generation: u64) -> bool {
+        self.generations.get(tag) == Some(&generation)
+    }
+}
+
+/// Look up the IDs of the images `tags`, leaving out those the backend can't tell.
+fn image_ids(backend: &dyn Backend, tags: &[String]) -> HashMap<String, String> {
+    let mut ids = HashMap::new();
+    for tag in tags {
+        match backend.image_id(tag) {
+            Ok(id) => {
+                ids.insert(tag.clone(), id);
+            }
+            Err(err) => debug!(tag = %tag, error = %err, "No image ID"),
+        }
+    }
+    ids
+}
+
+fn pod_server(
+    factory: PodFactory,
+    warm: Arc<AtomicUsize>,
+    req_ch: mpsc::Receiver<Request>,
+    resp_ch: mpsc::Sender<Response>,
+) {
+    // Reset pods are handed out before the fresh one,
+    // which only gets created when there are no reset pods
+    let mut fresh: Option<Response> = None;
+    let mut idle: Vec<Pod> = Vec::new();
     loop {
-        let pod_res = Pod::new_from_tag(&tag);
+        if factory.spare && fresh.is_none() && idle.is_empty() {
+            fresh = Some(factory.create());
+        }
 
-        let req = req_ch.recv().unwrap();
-        match req {
-            Request::Terminate => return,
-            Request::CreatePod => (),
+        let fresh_count = matches!(fresh, Some(Ok(_))) as usize;
+        warm.store(idle.len() + fresh_count, Ordering::SeqCst);
+
+        match req_ch.recv() {
+            Ok(Request::CreatePod) => {
+                let resp = match idle.pop() {
+                    Some(pod) => Ok(pod),
+                    None => fresh.take().unwrap_or_else(|| factory.create()),
+                };
+                warm.fetch_sub(resp.is_ok() as usize, Ordering::SeqCst);
+                if resp_ch.send(resp).is_err() {
+                    return;
+                }
+            }
+            Ok(Request::Recycled(pod)) => {
+                // Anything beyond that gets dropped and killed
+                if idle.len() < MAX_IDLE_PODS {
+                    debug!(container = %pod.id, uses = pod.uses, "Reusing container");
+                    idle.push(pod);
+                }
+            }
+            Ok(Request::Terminate) | Err(_) => return,
         }
+    }
+}
+
+struct WarmState {
+    idle: Vec<Pod>,
+    /// When the language was requested, oldest first
+    requests: VecDeque<time::Instant>,
+    closed: bool,
+}
 
-        resp_ch.send(pod_res).unwrap();
+/// Pods which have been primed for one language, see `LANGBOT_WARM_LANGS`.
+/// The number of pods kept warm follows the language's recent request rate.
+struct WarmPool {
+    language: String,
+    max: usize,
+    state: Mutex<WarmState>,
+    cond: Condvar,
+}
+
+impl WarmPool {
+    fn new(language: String, max: usize) -> Self {
+        Self {
+            language,
+            max,
+            state: Mutex::new(WarmState {
+                idle: Vec::new(),
+                requests: VecDeque::new(),
+                closed: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn target(&self, state: &mut WarmState) -> usize {
+        let now = time::Instant::now();
+        while let Some(&at) = state.requests.front() {
+            if now.duration_since(at) < RATE_WINDOW {
+                break;
+            }
+            state.requests.pop_front();
+        }
+
+        (1 + state.requests.len() / REQUESTS_PER_POD).min(self.max)
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+
+    /// Take a primed pod if there is one. Counts the request either way.
+    fn take(&self) -> Option<Pod> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push_back(time::Instant::now());
+        let pod = state.idle.pop();
+        self.cond.notify_all();
+        pod
+    }
+
+    /// Add a pod to the pool, or give it back if the pool is full.
+    fn put(&self, pod: Pod) -> Result<(), Pod> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed || state.idle.len() >= self.target(&mut state) {
+            return Err(pod);
+        }
+
+        state.idle.push(pod);
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Throw away the idle pods, e.g because their image was replaced.
+    fn drain(&self) {
+        let mut state = self.state.lock().unwrap();
+        let idle = std::mem::take(&mut state.idle);
+        drop(state);
+        self.cond.notify_all();
+        drop(idle);
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let idle = std::mem::take(&mut state.idle);
+        drop(state);
+        self.cond.notify_all();
+
+        // Kills the pods
+        drop(idle);
     }
 }
 
-pub struct PodManager {
-    server: Option<thread::JoinHandle<()>>,
-    req_ch: mpsc::Sender<Request>,
-    resp_ch: mpsc::Receiver<Response>,
+/// Keeps a warm pool filled up to its target, and shrinks it when requests die down.
+fn warm_pool_filler(factory: PodFactory, routing: SharedRouting, pool: Arc<WarmPool>) {
+    let mut backoff = time::Duration::from_secs(1);
+    loop {
+        let mut state = pool.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return;
+            }
+
+            let target = pool.target(&mut state);
+            if state.idle.len() < target {
+                break;
+            }
+
+            if state.idle.len() > target {
+                let pod = state.idle.remove(0);
+                drop(state);
+                debug!(language = %pool.language, container = %pod.id, "Shrinking warm pool");
+                drop(pod);
+                state = pool.state.lock().unwrap();
+                continue;
+            }
+
+            // Wake up now and then to shrink the pool if there are no more requests
+            state = pool.cond.wait_timeout(state, time::Duration::from_secs(30)).unwrap().0;
+        }
+        drop(state);
+
+        let start = time::Instant::now();
+        let (tag, generation) = routing.read().unwrap().current(&pool.language);
+        let res = factory.for_image(&tag, generation).create().and_then(|mut pod| {
+            pod.prime(&pool.language)?;
+            Ok(pod)
+        });
+        match res {
+            Ok(pod) => {
+                debug!(
+                    language = %pool.language,
+                    container = %pod.id,
+                    elapsed = ?start.elapsed(),
+                    "Primed container"
+                );
+                backoff = time::Duration::from_secs(1);
+
+                // If the pool filled up or the image was replaced in the mean time,
+                // the pod is dropped and killed
+                if routing.read().unwrap().is_current(&pod.tag, pod.generation) {
+                    let _ = pool.put(pod);
+                }
+            }
+            Err(err) => {
+                warn!(language = %pool.language, error = %err, "Creating warm pod failed");
+                let state = pool.state.lock().unwrap();
+                let _ = pool.cond.wait_timeout_while(state, backoff, |state| !state.closed);
+                backoff = (backoff * 2).min(time::Duration::from_secs(60));
+            }
+        }
+    }
 }
 
-unsafe impl Send for PodManager {}
-unsafe impl Sync for PodManager {}
+/// The pod server for one generation of one image.
+struct ImageServer {
+    generation: u64,
+    handle: Option<thread::JoinHandle<()>>,
+    warm: Arc<AtomicUsize>,
+    req_ch: mpsc::Sender<Request>,
+    resp_ch: Mutex<mpsc::Receiver<Response>>,
+}
 
-impl PodManager {
-    pub fn new(tag: String) -> Self {
+impl ImageServer {
+    fn new(factory: PodFactory) -> Self {
         let (req_send, req_recv) = mpsc::channel();
         let (resp_send, resp_recv) = mpsc::channel();
+        let warm = Arc::new(AtomicUsize::new(0));
+
+        let generation = factory.generation;
+        let warm_th = warm.clone();
         let handle = thread::spawn(move || {
-            pod_server(tag, req_recv, resp_send);
+            pod_server(factory, warm_th, req_recv, resp_send);
         });
 
         Self {
-            server: Some(handle),
+            generation,
+            handle: Some(handle),
+            warm,
             req_ch: req_send,
-            resp_ch: resp_recv,
+            resp_ch: Mutex::new(resp_recv),
+        }
+    }
+
+    fn get_pod(&self) -> Result<Pod, String> {
+        let resp_ch = self.resp_ch.lock().unwrap();
+        if self.req_ch.send(Request::CreatePod).is_err() {
+            return Err("The pod manager is shut down".into());
+        }
+
+        match resp_ch.recv() {
+            Ok(resp) => resp,
+            Err(_) => Err("The pod manager is shut down".into()),
+        }
+    }
+
+    fn terminate(&self) {
+        let _ = self.req_ch.send(Request::Terminate);
+    }
+}
+
+impl Drop for ImageServer {
+    fn drop(&mut self) {
+        self.terminate();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
         }
     }
+}
+
+/// Create a pod from `factory` and run the self-test of `languages` in it,
+/// or of every language in the image if it's empty.
+fn test_image(factory: &PodFactory, languages: &[String]) -> Result<selftest::Report, String> {
+    let mut pod = factory.create()?;
+    pod.self_test(languages)
+}
 
-    pub fn get_pod(&self) -> Result<Pod, String> {
-        self.req_ch.send(Request::CreatePod).unwrap();
-        self.resp_ch.recv().unwrap()
+/// Whether testing `languages` in the image `tag`, or every language routed to it
+/// if it's empty, tests `language`.
+fn is_tested(router: &Router, tag: &str, languages: &[String], language: &str) -> bool {
+    router.image(language) == tag && (languages.is_empty() || languages.iter().any(|l| l == language))
+}
+
+/// Self-test every image we start with, and disable the languages which don't work.
+fn self_tester(factory: PodFactory, routing: SharedRouting, registry: Arc<Registry>) {
+    let initial = routing.read().unwrap().clone();
+    for tag in initial.router.images() {
+        let generation = initial.generations[&tag];
+        let start = time::Instant::now();
+        let report = match test_image(&factory.for_image(&tag, generation), &initial.router.languages(&tag)) {
+            Ok(report) => report,
+            Err(err) => {
+                warn!(tag = %tag, error = %err, "Self-testing image failed");
+                continue;
+            }
+        };
+        info!(
+            tag = %tag,
+            passed = report.passed.len(),
+            failed = report.failed.len(),
+            skipped = report.skipped.len(),
+            elapsed = ?start.elapsed(),
+            "Self-tested image"
+        );
+
+        // The image might have been switched in the meantime
+        let routing = routing.read().unwrap();
+        registry.record(&report, |lang| routing.current(lang) == (tag.clone(), generation));
+    }
+}
+
+pub struct PodManager {
+    backend: Arc<dyn Backend>,
+    factory: PodFactory,
+    routing: SharedRouting,
+    languages: Arc<Registry>,
+    servers: RwLock<HashMap<String, Arc<ImageServer>>>,
+    reaper: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+    live: LiveSet,
+    max_uses: u32,
+    checkpoints: Checkpoints,
+    checkpoint_dir: PathBuf,
+    pools: HashMap<String, Arc<WarmPool>>,
+    fillers: Vec<thread::JoinHandle<()>>,
+    /// Held while switching images, so that only one switch happens at a time
+    switching: Mutex<u64>,
+    closed: AtomicBool,
+}
+
+impl PodManager {
+    pub fn new(backend: Arc<dyn Backend>, router: Router) -> Self {
+        Self::with_mode(backend, router, false)
+    }
+
+    /// A pod manager for tools which run code the way the bot does, possibly next to
+    /// a running bot. Its containers get STANDALONE_LABEL so that the bot doesn't reap them,
+    /// it doesn't reap any containers itself, and it doesn't self-test the images.
+    /// Containers are only started when a pod is requested.
+    pub fn standalone(backend: Arc<dyn Backend>, router: Router) -> Self {
+        Self::with_mode(backend, router, true)
+    }
+
+    fn with_mode(backend: Arc<dyn Backend>, router: Router, standalone: bool) -> Self {
+        let live: LiveSet = Arc::new(Mutex::new(Live::default()));
+
+        let label = match standalone {
+            true => (STANDALONE_LABEL, std::process::id().to_string()),
+            false => (INSTANCE_LABEL, instance_id()),
+        };
+        info!(instance = %label.1, "Starting pod manager");
+
+        let mut reaper_handle = None;
+        if !standalone {
+            let state_dir = env::var("LANGBOT_STATE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("langbot-instances"));
+            let instance = Instance::register(&state_dir, label.1.clone())
+                .unwrap_or_else(|err| panic!("Registering instance failed: {}", err));
+
+            // Clean up after a previous instance before we start creating containers
+            reap_and_log(backend.as_ref(), &instance, &live);
+
+            let reap_interval = env::var("LANGBOT_REAP_INTERVAL")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(300);
+            let (stop_send, stop_recv) = mpsc::channel();
+            let backend_th = backend.clone();
+            let live_th = live.clone();
+            let handle = thread::spawn(move || {
+                let interval = time::Duration::from_secs(reap_interval);
+                reaper(backend_th, instance, live_th, interval, stop_recv);
+            });
+            reaper_handle = Some((stop_send, handle));
+        }
+
+        let max_uses = env::var("LANGBOT_POD_REUSE")
+            .ok()
+            .and_then(|uses| uses.parse().ok())
+            .unwrap_or(1)
+            .max(1);
+        if max_uses > 1 {
+            info!(max_uses, "Reusing containers");
+        }
+
+        let routing: SharedRouting = Arc::new(RwLock::new(Routing {
+            generations: router.images().into_iter().map(|tag| (tag, 0)).collect(),
+            ids: image_ids(backend.as_ref(), &router.images()),
+            router,
+        }));
+        let factory = PodFactory {
+            backend: backend.clone(),
+            tag: String::new(),
+            generation: 0,
+            label,
+            live: live.clone(),
+            reuse: max_uses > 1,
+            spare: !standalone,
+        };
+
+        let checkpoints: Checkpoints = Arc::new(Mutex::new(HashMap::new()));
+        let checkpoint_dir = env::var("LANGBOT_CHECKPOINT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| env::temp_dir().join("langbot-checkpoints"));
+        if let Ok(languages) = env::var("LANGBOT_CHECKPOINT_LANGS") {
+            let mut lock = checkpoints.lock().unwrap();
+            for language in languages.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+                lock.insert(language.to_string(), CheckpointState::Pending);
+            }
+            if max_uses > 1 && !lock.is_empty() {
+                info!(
+                    languages = ?lock.keys().collect::<Vec<_>>(),
+                    "Checkpointed languages don't reuse containers"
+                );
+            }
+        }
+
+        let max_warm = env::var("LANGBOT_WARM_MAX")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(4);
+        let mut pools = HashMap::new();
+        let mut fillers = Vec::new();
+        if let Ok(languages) = env::var("LANGBOT_WARM_LANGS") {
+            for language in languages.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+                let pool = Arc::new(WarmPool::new(language.to_string(), max_warm));
+                let factory_th = factory.clone();
+                let routing_th = routing.clone();
+                let pool_th = pool.clone();
+                fillers.push(thread::spawn(move || {
+                    warm_pool_filler(factory_th, routing_th, pool_th)
+                }));
+                pools.insert(language.to_string(), pool);
+            }
+        }
+
+        let servers = routing
+            .read()
+            .unwrap()
+            .router
+            .images()
+            .into_iter()
+            .map(|tag| {
+                let server = ImageServer::new(factory.for_image(&tag, 0));
+                (tag, Arc::new(server))
+            })
+            .collect();
+
+        let podman = Self {
+            backend,
+            factory,
+            routing,
+            languages: Arc::new(Registry::default()),
+            servers: RwLock::new(servers),
+            reaper: reaper_handle,
+            live,
+            max_uses,
+            checkpoints,
+            checkpoint_dir,
+            pools,
+            fillers,
+            switching: Mutex::new(0),
+            closed: AtomicBool::new(false),
+        };
+
+        let languages: Vec<String> = podman.checkpoints.lock().unwrap().keys().cloned().collect();
+        podman.start_checkpointer(languages);
+        if standalone {
+            return podman;
+        }
+
+        let factory = podman.factory.clone();
+        let routing = podman.routing.clone();
+        let registry = podman.languages.clone();
+        thread::spawn(move || self_tester(factory, routing, registry));
+
+        podman
+    }
+
+    fn start_checkpointer(&self, languages: Vec<String>) {
+        if languages.is_empty() {
+            return;
+        }
+
+        let factory = PodFactory {
+            reuse: false,
+            ..self.factory.clone()
+        };
+        let routing = self.routing.clone();
+        let checkpoints = self.checkpoints.clone();
+        let dir = self.checkpoint_dir.clone();
+        thread::spawn(move || checkpointer(factory, routing, checkpoints, dir, languages));
+    }
+
+    /// Number of started pods which are ready to be handed out right away.
+    pub fn warm_pods(&self) -> usize {
+        let pooled: usize = self.pools.values().map(|pool| pool.len()).sum();
+        let fresh: usize = self
+            .servers
+            .read()
+            .unwrap()
+            .values()
+            .map(|server| server.warm.load(Ordering::SeqCst))
+            .sum();
+        fresh + pooled
+    }
+
+    /// The ID of the image `language` runs in right now, if the backend has image IDs.
+    pub fn image_id(&self, language: &str) -> Option<String> {
+        let routing = self.routing.read().unwrap();
+        routing.ids.get(routing.router.image(language)).cloned()
+    }
+
+    /// The registry of disabled languages.
+    pub fn languages(&self) -> Arc<Registry> {
+        self.languages.clone()
+    }
+
+    /// Check that we can talk to the container engine at all.
+    pub fn ping(&self) -> Result<(), String> {
+        self.backend.ping()
+    }
+
+    /// Fail if `language` is disabled because it failed its self-test.
+    pub fn check_enabled(&self, language: &str) -> Result<(), String> {
+        match self.languages.why_disabled(language) {
+            Some(reason) => Err(format!("{} is disabled, it failed its self-test: {}", language, reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a pod to run `language` in. That's a pod from the language's warm pool if there is one,
+    /// or a pod restored from the language's checkpoint if there is one,
+    /// or a generic pod from the language's image.
+    pub fn get_pod(&self, language: &str) -> Result<Pod, String> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("The pod manager is shut down".into());
+        }
+
+        self.check_enabled(language)?;
+
+        if let Some(pod) = self.pools.get(language).and_then(|pool| pool.take()) {
+            debug!(container = %pod.id, "Using warm pod");
+            return Ok(pod);
+        }
+
+        let (tag, generation) = self.routing.read().unwrap().current(language);
+        let checkpoint = match self.checkpoints.lock().unwrap().get(language) {
+            Some(CheckpointState::Ready {
+                path,
+                tag: checkpoint_tag,
+                generation: checkpoint_generation,
+            }) if *checkpoint_tag == tag && *checkpoint_generation == generation => {
+                Some(path.clone())
+            }
+            _ => None,
+        };
+        if let Some(path) = checkpoint {
+            let backend = self.backend.clone();
+            match Pod::new_from_checkpoint(backend, &tag, generation, &path, self.live.clone()) {
+                Ok(pod) => return Ok(pod),
+                Err(err) => warn!(error = %err, "Restoring container failed"),
+            }
+        }
+
+        // Don't hold the lock while waiting for a pod
+        let server = self.servers.read().unwrap().get(&tag).cloned();
+        match server {
+            Some(server) => server.get_pod(),
+            None => Err(format!("No pod server for image {}", tag)),
+        }
+    }
+
+    /// Give back a pod after an execution. If it can be reused, it's reset in the background
+    /// and handed out again; otherwise, or if resetting it fails, it's killed.
+    pub fn recycle(&self, mut pod: Pod) {
+        if !pod.reusable(self.max_uses) || self.closed.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let server = self.servers.read().unwrap().get(&pod.tag).cloned();
+        let req_ch = match server {
+            Some(server) if server.generation == pod.generation => server.req_ch.clone(),
+            // The image was replaced, so the pod is dropped and killed
+            _ => return,
+        };
+
+        let routing = self.routing.clone();
+        let pool = pod.language.as_ref().and_then(|lang| self.pools.get(lang)).cloned();
+        thread::spawn(move || {
+            let start = time::Instant::now();
+            if let Err(err) = pod.reset() {
+                warn!(container = %pod.id, error = %err, "Resetting container failed, discarding it");
+                return;
+            }
+            debug!(container = %pod.id, elapsed = ?start.elapsed(), "Reset container");
+
+            // The language's files are still cached, so prefer its warm pool,
+            // if the language still runs in this image
+            let pod = match pool {
+                Some(pool) if routing.read().unwrap().current(&pool.language) == (pod.tag.clone(), pod.generation) => {
+                    match pool.put(pod) {
+                        Ok(()) => return,
+                        Err(pod) => pod,
+                    }
+                }
+                _ => pod,
+            };
+
+            // If the server is gone, the pod is dropped and killed
+            let _ = req_ch.send(Request::Recycled(pod));
+        });
+    }
+
+    /// Switch to a new routing table. Images which weren't in the old table are started
+    /// and self-tested first, along with the images in `rebuilt`, which are images
+    /// that are in both but whose tag now refers to a new build. Images whose ID changed
+    /// are treated as rebuilt too. Languages which move to an image that's already running
+    /// are self-tested in it. New requests only go to the new images once they've all passed,
+    /// and pods of images which aren't used any more are drained.
+    /// If any of them fails, nothing changes.
+    pub fn switch_routes(&self, router: Router, rebuilt: &[String]) -> Result<(), String> {
+        let mut generation_counter = self.switching.lock().unwrap();
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("The pod manager is shut down".into());
+        }
+
+        let old = self.routing.read().unwrap().clone();
+        let kept: Vec<String> = router
+            .images()
+            .into_iter()
+            .filter(|tag| old.generations.contains_key(tag))
+            .collect();
+        // A tag which refers to a different image now was rebuilt, whether we were told or not
+        let rebuilt: Vec<String> = image_ids(self.backend.as_ref(), &kept)
+            .into_iter()
+            .filter(|(tag, id)| old.ids.get(tag).is_some_and(|old_id| old_id != id))
+            .map(|(tag, _)| tag)
+            .chain(rebuilt.iter().cloned())
+            .collect();
+        let started: Vec<String> = router
+            .images()
+            .into_iter()
+            .filter(|tag| !old.generations.contains_key(tag) || rebuilt.contains(tag))
+            .collect();
+
+        // Start the new pod servers right away so that they're warm when we switch
+        *generation_counter += 1;
+        let generation = *generation_counter;
+        let mut new_servers = HashMap::new();
+        // The images to test, and which languages, or all of those routed to it if it's empty
+        let mut tests = Vec::new();
+        for tag in &started {
+            let factory = self.factory.for_image(tag, generation);
+            new_servers.insert(tag.clone(), Arc::new(ImageServer::new(factory.clone())));
+            tests.push((tag.clone(), factory, router.languages(tag)));
+        }
+        let mut moved: HashMap<String, Vec<String>> = HashMap::new();
+        for language in old.router.moved(&router, &Registry::names()) {
+            let tag = router.image(&language);
+            if !started.iter().any(|started| started == tag) {
+                moved.entry(tag.to_string()).or_default().push(language);
+            }
+        }
+        for (tag, languages) in moved {
+            let factory = self.factory.for_image(&tag, old.generations[&tag]);
+            tests.push((tag, factory, languages));
+        }
+
+        let mut reports = Vec::new();
+        for (tag, factory, languages) in &tests {
+            let owns = |lang: &str| is_tested(&router, tag, languages, lang);
+
+            info!(tag = %tag, languages = ?languages, "Testing image");
+            let start = time::Instant::now();
+            // Languages which are already disabled may stay broken, but nothing else may break
+            let res = test_image(factory, languages).and_then(|report| {
+                let regressions = self.languages.regressions(&report, owns);
+                if report.passed.is_empty() && started.contains(tag) {
+                    Err("No language passed its self-test".to_string())
+                } else if !regressions.is_empty() {
+                    let failed = selftest::Report {
+                        failed: regressions,
+                        ..Default::default()
+                    };
+                    Err(format!("Self-test failed: {}", failed.failures()))
+                } else {
+                    Ok(report)
+                }
+            });
+            match res {
+                Ok(report) => {
+                    info!(
+                        tag = %tag,
+                        passed = report.passed.len(),
+                        failed = report.failed.len(),
+                        skipped = report.skipped.len(),
+                        elapsed = ?start.elapsed(),
+                        "Image passed its self-test"
+                    );
+                    reports.push((tag.clone(), languages.clone(), report));
+                }
+                Err(err) => {
+                    warn!(tag = %tag, error = %err, "Image failed its self-test, keeping the old images");
+                    // Dropping new_servers kills their pods
+                    return Err(format!("{}: {}", tag, err));
+                }
+            }
+        }
+
+        let mut generations = HashMap::new();
+        let mut ids = image_ids(self.backend.as_ref(), &started);
+        for tag in router.images() {
+            let generation = match new_servers.contains_key(&tag) {
+                true => generation,
+                false => {
+                    if let Some(id) = old.ids.get(&tag) {
+                        ids.insert(tag.clone(), id.clone());
+                    }
+                    old.generations[&tag]
+                }
+            };
+            generations.insert(tag, generation);
+        }
+        let new = Routing {
+            router,
+            generations,
+            ids,
+        };
+
+        let mut retired = Vec::new();
+        {
+            let mut servers = self.servers.write().unwrap();
+            let mut routing = self.routing.write().unwrap();
+            for (tag, server) in new_servers {
+                retired.extend(servers.insert(tag, server));
+            }
+            let gone: Vec<String> = servers
+                .keys()
+                .filter(|tag| !new.generations.contains_key(*tag))
+                .cloned()
+                .collect();
+            for tag in gone {
+                retired.extend(servers.remove(&tag));
+            }
+            *routing = new.clone();
+        }
+        info!(images = ?new.router.images(), "Switched images");
+
+        for (tag, languages, report) in &reports {
+            self.languages.record(report, |lang| is_tested(&new.router, tag, languages, lang));
+        }
+
+        // Pods of the old images which are in use get killed once they're done
+        for server in &retired {
+            server.terminate();
+        }
+        thread::spawn(move || drop(retired));
+
+        for pool in self.pools.values() {
+            if old.current(&pool.language) != new.current(&pool.language) {
+                pool.drain();
+            }
+        }
+
+        let mut stale = Vec::new();
+        for (language, state) in self.checkpoints.lock().unwrap().iter_mut() {
+            if old.current(language) != new.current(language) {
+                *state = CheckpointState::Pending;
+                stale.push(language.clone());
+            }
+        }
+        self.start_checkpointer(stale);
+
+        Ok(())
+    }
+
+    /// Replace the image `from` with `to` wherever it's used. If they're the same,
+    /// the image is treated as rebuilt, see `switch_routes`.
+    pub fn switch_image(&self, from: &str, to: &str) -> Result<(), String> {
+        let router = self.routing.read().unwrap().router.clone();
+        if !router.images().iter().any(|tag| tag == from) {
+            return Err(format!("{} isn't in use", from));
+        }
+
+        self.switch_routes(router.replace(from, to), &[to.to_string()])
+    }
+
+    /// Stop handing out pods. Anyone waiting in get_pod will get an error.
+    pub fn shutdown(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for server in self.servers.read().unwrap().values() {
+            server.terminate();
+        }
+        for pool in self.pools.values() {
+            pool.close();
+        }
+    }
+
+    /// Kill every container which is still alive, including those in use.
+    pub fn kill_all(&self) {
+        let ids: Vec<String> = self.live.lock().unwrap().ids.iter().cloned().collect();
+        for id in ids {
+            info!(container = %id, "Killing container");
+            if let Err(err) = self.backend.kill(&id) {
+                warn!(container = %id, error = %err, "Killing container failed");
+            }
+        }
     }
 }
 
 impl Drop for PodManager {
     fn drop(&mut self) {
-        self.req_ch.send(Request::Terminate).unwrap();
-        self.server.take().unwrap().join().unwrap();
+        self.shutdown();
+        // Dropping the servers joins their threads
+        self.servers.write().unwrap().clear();
+        for filler in self.fillers.drain(..) {
+            filler.join().unwrap();
+        }
+
+        if let Some((stop_ch, reaper)) = self.reaper.take() {
+            drop(stop_ch);
+            reaper.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ExecOutput;
+
+    /// Containers with one label each, which can only be listed and removed.
+    struct Labelled {
+        containers: Mutex<Vec<(String, String)>>,
+    }
+
+    impl Backend for Labelled {
+        fn name(&self) -> &'static str {
+            "labelled"
+        }
+
+        fn ping(&self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn create(&self, _tag: &str, _labels: &[(&str, &str)]) -> Result<String, String> {
+            Err("Not supported".into())
+        }
+
+        fn run(
+            &self,
+            _id: &str,
+            _language: &str,
+            _args: &[String],
+            _stdin: &[u8],
+        ) -> Result<ExecOutput, String> {
+            Err("Not supported".into())
+        }
+
+        fn files(&self, _id: &str, _language: &str) -> Result<Vec<u8>, String> {
+            Err("Not supported".into())
+        }
+
+        fn prime(&self, _id: &str, _language: &str) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn self_test(&self, _id: &str, _languages: &[String]) -> Result<String, String> {
+            Err("Not supported".into())
+        }
+
+        fn snapshot(&self, _id: &str) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn reset(&self, _id: &str) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn kill(&self, _id: &str) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn list(&self, key: &str) -> Result<Vec<String>, String> {
+            let containers = self.containers.lock().unwrap();
+            Ok(containers
+                .iter()
+                .filter(|(_, label)| label == key)
+                .map(|(id, _)| id.clone())
+                .collect())
+        }
+
+        fn remove(&self, ids: &[String]) -> Result<(), String> {
+            self.containers.lock().unwrap().retain(|(id, _)| !ids.contains(id));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reaps_only_dead_instances() {
+        let dir = env::temp_dir().join(format!("langbot-instances-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let us = Instance::register(&dir, "us".into()).unwrap();
+        let _running = Instance::register(&dir, "running".into()).unwrap();
+        assert!(Instance::register(&dir, "running".into()).is_err());
+        drop(Instance::register(&dir, "dead".into()).unwrap());
+
+        let label = |instance: &str| format!("{}={}", INSTANCE_LABEL, instance);
+        let backend = Labelled {
+            containers: Mutex::new(vec![
+                ("live".into(), label("us")),
+                ("orphan".into(), label("us")),
+                ("other".into(), label("running")),
+                ("dead".into(), label("dead")),
+                ("unknown".into(), label("unregistered")),
+            ]),
+        };
+        let live = Mutex::new(Live::default());
+        live.lock().unwrap().ids.insert("live".into());
+
+        // Our own containers are left alone while one is being created
+        live.lock().unwrap().creating = 1;
+        assert_eq!(reap(&backend, &us, &live).unwrap(), 1);
+        assert!(!dir.join("dead.lock").exists());
+        live.lock().unwrap().creating = 0;
+        assert_eq!(reap(&backend, &us, &live).unwrap(), 1);
+
+        let left: Vec<String> =
+            backend.containers.lock().unwrap().iter().map(|(id, _)| id.clone()).collect();
+        assert_eq!(left, vec!["live", "other", "unknown"]);
+        assert!(dir.join("running.lock").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn warm_pool_follows_request_rate() {
+        let pool = WarmPool::new("haskell".into(), 3);
+        let mut state = pool.state.lock().unwrap();
+        assert_eq!(pool.target(&mut state), 1);
+
+        let now = time::Instant::now();
+        state.requests.extend(std::iter::repeat_n(now, REQUESTS_PER_POD));
+        assert_eq!(pool.target(&mut state), 2);
+
+        state.requests.extend(std::iter::repeat_n(now, REQUESTS_PER_POD * 10));
+        assert_eq!(pool.target(&mut state), 3);
+
+        // Requests which are too old don't count
+        if let Some(old) = now.checked_sub(RATE_WINDOW * 2) {
+            state.requests = std::iter::repeat_n(old, REQUESTS_PER_POD * 10).collect();
+            assert_eq!(pool.target(&mut state), 1);
+            assert!(state.requests.is_empty());
+        }
     }
 }
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::thread;
+use std::time;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// The image used for every language when there's no routing table.
+pub const DEFAULT_IMAGE: &str = "langbot";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoutesFile {
+    default: Option<String>,
+    #[serde(default)]
+    images: HashMap<String, Vec<String>>,
+}
+
+/// Decides which image each language runs in, so that languages can be split
+/// into images which are built and deployed independently.
+#[derive(Clone)]
+pub struct Router {
+    default: String,
+    routes: HashMap<String, String>,
+}
+
+impl Router {
+    /// Route every language to `default`.
+    pub fn single(default: impl Into<String>) -> Self {
+        Self {
+            default: default.into(),
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Parse a routing table like:
+    ///
+    /// ```toml
+    /// default = "langbot"
+    ///
+    /// [images]
+    /// langbot-lean = ["lean"]
+    /// langbot-ml = ["haskell", "ocaml"]
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: RoutesFile = match toml::from_str(text) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Invalid routing table: {}", err)),
+        };
+
+        let mut routes = HashMap::new();
+        for (image, languages) in file.images {
+            for language in languages {
+                if let Some(other) = routes.insert(language.clone(), image.clone()) {
+                    return Err(format!(
+                        "Invalid routing table: {} is routed to both {} and {}",
+                        language, other, image
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            default: file.default.unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+            routes,
+        })
+    }
+
+    /// Read the routing table from the file named by `LANGBOT_ROUTES`,
+    /// or route everything to the default image if it's not set.
+    pub fn from_env() -> Self {
+        let path = match env::var("LANGBOT_ROUTES") {
+            Ok(path) if !path.is_empty() => path,
+            _ => return Self::single(DEFAULT_IMAGE),
+        };
+
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Reading LANGBOT_ROUTES file {} failed: {}", path, err));
+        Self::parse(&text).unwrap_or_else(|err| panic!("{}: {}", path, err))
+    }
+
+    /// Watch the file named by `LANGBOT_ROUTES`, and call `on_change` with the new table
+    /// whenever it changes, until `on_change` returns false.
+    /// Invalid tables are logged and ignored.
+    pub fn watch(interval: time::Duration, mut on_change: impl FnMut(Self) -> bool + Send + 'static) {
+        let path = match env::var("LANGBOT_ROUTES") {
+            Ok(path) if !path.is_empty() => path,
+            _ => return,
+        };
+
+        let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        thread::spawn(move || {
+            let mut last = modified(&path);
+            loop {
+                thread::sleep(interval);
+                let current = modified(&path);
+                if current == last {
+                    continue;
+                }
+                last = current;
+
+                let router = fs::read_to_string(&path)
+                    .map_err(|err| format!("Reading {} failed: {}", path, err))
+                    .and_then(|text| Self::parse(&text));
+                match router {
+                    Ok(router) => {
+                        info!(path = %path, "Routing table changed");
+                        if !on_change(router) {
+                            return;
+                        }
+                    }
+                    Err(err) => warn!(path = %path, error = %err, "Ignoring routing table"),
+                }
+            }
+        });
+    }
+
+    pub fn image(&self, language: &str) -> &str {
+        self.routes.get(language).unwrap_or(&self.default)
+    }
+
+    /// The languages routed to `image`. Empty for the default image,
+    /// which gets every language that isn't routed anywhere else.
+    pub fn languages(&self, image: &str) -> Vec<String> {
+        if image == self.default {
+            return Vec::new();
+        }
+
+        let mut languages: Vec<String> = self
+            .routes
+            .iter()
+            .filter(|(_, tag)| *tag == image)
+            .map(|(language, _)| language.clone())
+            .collect();
+        languages.sort();
+        languages
+    }
+
+    /// The same table with the image `from` replaced by `to`.
+    pub fn replace(&self, from: &str, to: &str) -> Self {
+        let swap = |tag: &String| if tag == from { to.to_string() } else { tag.clone() };
+        Self {
+            default: swap(&self.default),
+            routes: self
+                .routes
+                .iter()
+                .map(|(language, tag)| (language.clone(), swap(tag)))
+                .collect(),
+        }
+    }
+
+    /// The languages which run in a different image with `other`, out of `languages`
+    /// and those which either table routes, sorted.
+    pub fn moved(&self, other: &Self, languages: &[String]) -> Vec<String> {
+        let mut moved: Vec<String> = languages
+            .iter()
+            .chain(self.routes.keys())
+            .chain(other.routes.keys())
+            .filter(|language| self.image(language) != other.image(language))
+            .cloned()
+            .collect();
+        moved.sort();
+        moved.dedup();
+        moved
+    }
+
+    /// Every image in the table, the default one first.
+    pub fn images(&self) -> Vec<String> {
+        let mut images = vec![self.default.clone()];
+        for image in self.routes.values() {
+            if !images.contains(image) {
+                images.push(image.clone());
+            }
+        }
+        images
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_languages_to_images() {
+        let router = Router::parse(
+            r#"
+            default = "langbot-base"
+
+            [images]
+            langbot-lean = ["lean"]
+            langbot-ml = ["haskell", "ocaml"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(router.image("lean"), "langbot-lean");
+        assert_eq!(router.image("ocaml"), "langbot-ml");
+        assert_eq!(router.image("python"), "langbot-base");
+
+        let mut images = router.images();
+        assert_eq!(images.remove(0), "langbot-base");
+        images.sort();
+        assert_eq!(images, vec!["langbot-lean", "langbot-ml"]);
+    }
+
+    #[test]
+    fn finds_moved_languages() {
+        let old = Router::parse("[images]\nlangbot-ml = [\"haskell\", \"ocaml\"]\n").unwrap();
+        let new = Router::parse("[images]\nlangbot-ml = [\"ocaml\"]\nlangbot-lean = [\"lean\"]\n")
+            .unwrap();
+        let all = ["c".to_string(), "lean".to_string(), "ocaml".to_string()];
+        assert_eq!(old.moved(&new, &all), vec!["haskell", "lean"]);
+        assert_eq!(old.moved(&old, &all), Vec::<String>::new());
+        assert_eq!(old.moved(&old.replace("langbot", "langbot-ml"), &all), vec!["c", "lean"]);
+    }
+
+    #[test]
+    fn rejects_languages_in_two_images() {
+        let err = Router::parse(
+            r#"
+            [images]
+            a = ["lean"]
+            b = ["lean"]
+            "#,
+        )
+        .err()
+        .unwrap();
+        assert!(err.contains("lean is routed to both"), "{}", err);
+
+        assert_eq!(Router::parse("").unwrap().image("c"), DEFAULT_IMAGE);
+        assert!(Router::parse("defualt = \"x\"").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // What the bot accepts after the '!', lowercased
+    static ref NAME_RX: Regex = Regex::new(r"^[a-z][a-z0-9+_]*$").unwrap();
+}
+
+/// What a new language starts out as. Each template is a working language,
+/// which passes the linter and the conformance tests before it's changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// Runs source code with an interpreter from the base image
+    Interpreted,
+    /// Compiles source code with a compiler from the base image, then runs the program
+    Compiled,
+    /// Builds the language from a pinned git commit
+    GitSource,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "interpreted" => Ok(Self::Interpreted),
+            "compiled" => Ok(Self::Compiled),
+            "git-source" => Ok(Self::GitSource),
+            _ => Err(format!(
+                "Unknown template: {}, expected interpreted, compiled or git-source",
+                s
+            )),
+        }
+    }
+}
+
+macro_rules! template {
+    ($dir:literal) => {
+        [
+            ("compile.sh", include_str!(concat!("../templates/new-lang/", $dir, "/compile.sh"))),
+            ("run.sh", include_str!(concat!("../templates/new-lang/", $dir, "/run.sh"))),
+            ("hello-world.txt", include_str!(concat!("../templates/new-lang/", $dir, "/hello-world.txt"))),
+            ("language.toml", include_str!(concat!("../templates/new-lang/", $dir, "/language.toml"))),
+            ("tests/example.toml", include_str!(concat!("../templates/new-lang/", $dir, "/example.toml"))),
+        ]
+    };
+}
+
+impl Template {
+    /// The files of a new language called `name`, by their path in its directory.
+    pub fn files(self, name: &str) -> Vec<(&'static str, String)> {
+        let files = match self {
+            Self::Interpreted => template!("interpreted"),
+            Self::Compiled => template!("compiled"),
+            Self::GitSource => template!("git-source"),
+        };
+        files
+            .iter()
+            .map(|(path, content)| (*path, content.replace("@LANG@", name)))
+            .collect()
+    }
+}
+
+/// Create `langs_dir/name` from a template. Returns the paths of the files it created.
+pub fn create(langs_dir: &Path, name: &str, template: Template) -> Result<Vec<PathBuf>, String> {
+    if !NAME_RX.is_match(name) {
+        return Err(format!(
+            "Invalid language name: {}, it should be lowercase letters, digits, + and _",
+            name
+        ));
+    }
+
+    let dir = langs_dir.join(name);
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()));
+    }
+
+    let mut created = Vec::new();
+    for (path, content) in template.files(name) {
+        let path = dir.join(path);
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent).map_err(|err| format!("Creating {} failed: {}", parent.display(), err))?;
+        fs::write(&path, content).map_err(|err| format!("Writing {} failed: {}", path.display(), err))?;
+        created.push(path);
+    }
+
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint;
+    use crate::testsuite::Case;
+
+    #[test]
+    fn templates_pass_the_linter() {
+        let langs_dir = std::env::temp_dir().join(format!("langbot-scaffold-{}", std::process::id()));
+        for (name, template) in [("a", "interpreted"), ("b", "compiled"), ("c++", "git-source")] {
+            create(&langs_dir, name, template.parse().unwrap()).unwrap();
+
+            let dir = langs_dir.join(name);
+            assert_eq!(lint::lint_language(&dir, |_| false), Vec::new(), "{}", template);
+            let cases = Case::load_all(&langs_dir, &[name.to_string()]).unwrap();
+            assert!(cases.len() >= 2, "{}", template);
+        }
+
+        assert!(create(&langs_dir, "a", Template::Compiled).is_err());
+        assert!(create(&langs_dir, "Bad-Name", Template::Compiled).is_err());
+        fs::remove_dir_all(&langs_dir).unwrap();
+    }
+}
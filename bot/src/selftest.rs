@@ -0,0 +1,64 @@
+/// The result of running `scripts/self-test.sh` in a container.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub passed: Vec<String>,
+    /// Languages and why they failed
+    pub failed: Vec<(String, String)>,
+    /// Languages without a hello world example
+    pub skipped: Vec<String>,
+}
+
+impl Report {
+    pub fn parse(output: &str) -> Self {
+        let mut report = Report::default();
+        for line in output.lines() {
+            let mut parts = line.splitn(3, ' ');
+            let (kind, lang) = match (parts.next(), parts.next()) {
+                (Some(kind), Some(lang)) => (kind, lang.to_string()),
+                _ => continue,
+            };
+
+            match kind {
+                "ok" => report.passed.push(lang),
+                "fail" => report
+                    .failed
+                    .push((lang, parts.next().unwrap_or("failed").to_string())),
+                "skip" => report.skipped.push(lang),
+                _ => (),
+            }
+        }
+
+        report
+    }
+
+    /// A one line summary of the failures, like "lean (exit code 1), ruby (wrong output)".
+    pub fn failures(&self) -> String {
+        self.failed
+            .iter()
+            .map(|(lang, reason)| format!("{} ({})", lang, reason))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_report() {
+        let report = Report::parse("ok c\nfail lean exit code 1\nskip asm\n\nok python\nfail ruby\n");
+        assert_eq!(report.passed, vec!["c", "python"]);
+        assert_eq!(
+            report.failed,
+            vec![
+                ("lean".to_string(), "exit code 1".to_string()),
+                ("ruby".to_string(), "failed".to_string()),
+            ]
+        );
+        assert_eq!(report.skipped, vec!["asm"]);
+        assert_eq!(report.failures(), "lean (exit code 1), ruby (failed)");
+
+        assert_eq!(Report::parse(""), Report::default());
+    }
+}
@@ -0,0 +1,403 @@
+//! Keeps the full results of executions around, so that they can be linked to
+//! from Discord, where the output is cut short.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::backend::parse_size;
+use crate::discord::describe_exit;
+use crate::http::AppState;
+use crate::podmanager::{ExecResult, OutputFile};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub name: String,
+    pub content_base64: String,
+}
+
+/// Everything about an execution.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredResult {
+    pub language: String,
+    pub source: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// Like "Exit Code 0 (OK)"
+    pub exit: String,
+    pub files: Vec<StoredFile>,
+}
+
+impl StoredResult {
+    pub fn new(language: &str, source: &str, output: &ExecResult, files: &[OutputFile]) -> Self {
+        Self {
+            language: language.to_string(),
+            source: source.to_string(),
+            stdout: output.stdout.clone().unwrap_or_default(),
+            stderr: output.stderr.clone().unwrap_or_default(),
+            exit_code: output.status.code(),
+            signal: output.status.signal(),
+            exit: describe_exit(&output.status),
+            files: files
+                .iter()
+                .map(|file| StoredFile {
+                    name: file.name.clone(),
+                    content_base64: BASE64.encode(&file.content),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Results on disk, one JSON file per result, named by the hash of its content.
+pub struct ResultStore {
+    dir: PathBuf,
+    /// Results which haven't been stored or looked at for this long are deleted
+    max_age: time::Duration,
+    /// The oldest results are deleted when they take up more than this many bytes
+    max_size: u64,
+    /// Results are linked to as `<public_url>/results/<id>`
+    public_url: String,
+    /// Guilds whose results aren't stored
+    opted_out: HashSet<u64>,
+}
+
+/// Whether `id` looks like something `ResultStore::save` returned,
+/// so that it's safe to use as a file name.
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|ch| ch.is_ascii_hexdigit() && !ch.is_ascii_uppercase())
+}
+
+impl ResultStore {
+    pub fn new(dir: PathBuf, max_age: time::Duration, max_size: u64, public_url: &str) -> Self {
+        Self {
+            dir,
+            max_age,
+            max_size,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            opted_out: HashSet::new(),
+        }
+    }
+
+    /// Don't store results from `guilds`.
+    pub fn opt_out(mut self, guilds: impl IntoIterator<Item = u64>) -> Self {
+        self.opted_out.extend(guilds);
+        self
+    }
+
+    /// Configure the store from `LANGBOT_RESULTS_DIR`, which enables it,
+    /// `LANGBOT_PUBLIC_URL`, `LANGBOT_RESULTS_MAX_AGE` (in days, default 30),
+    /// `LANGBOT_RESULTS_MAX_SIZE` (e.g `500m`, default `1g`)
+    /// and `LANGBOT_RESULTS_OPT_OUT` (comma separated guild IDs).
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|val| !val.is_empty());
+        let dir = PathBuf::from(var("LANGBOT_RESULTS_DIR")?);
+        let public_url = var("LANGBOT_PUBLIC_URL")
+            .expect("LANGBOT_RESULTS_DIR is set, but there's no LANGBOT_PUBLIC_URL to link to results with");
+        let max_age: u64 = var("LANGBOT_RESULTS_MAX_AGE").map_or(30, |val| {
+            val.parse().unwrap_or_else(|_| panic!("Invalid LANGBOT_RESULTS_MAX_AGE: {}", val))
+        });
+        let max_size = var("LANGBOT_RESULTS_MAX_SIZE").map_or(1024 * 1024 * 1024, |val| {
+            parse_size(&val).unwrap_or_else(|| panic!("Invalid LANGBOT_RESULTS_MAX_SIZE: {}", val))
+        });
+        let opted_out: Vec<u64> = var("LANGBOT_RESULTS_OPT_OUT")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse()
+                    .unwrap_or_else(|_| panic!("Invalid guild in LANGBOT_RESULTS_OPT_OUT: {}", id))
+            })
+            .collect();
+
+        fs::create_dir_all(&dir).unwrap_or_else(|err| {
+            panic!("Creating LANGBOT_RESULTS_DIR {} failed: {}", dir.display(), err)
+        });
+        let max_age = time::Duration::from_secs(max_age * 24 * 60 * 60);
+        Some(Self::new(dir, max_age, max_size, &public_url).opt_out(opted_out))
+    }
+
+    /// Whether results from `guild` should be stored. Direct messages have no guild,
+    /// and aren't stored, since there's no server which could opt out for them.
+    pub fn accepts(&self, guild: Option<u64>) -> bool {
+        guild.is_some_and(|guild| !self.opted_out.contains(&guild))
+    }
+
+    pub fn link(&self, id: &str) -> String {
+        format!("{}/results/{}", self.public_url, id)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Store `result`, and return its ID.
+    pub fn save(&self, result: &StoredResult) -> Result<String, String> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let json = serde_json::to_vec(result)
+            .map_err(|err| format!("Serializing result failed: {}", err))?;
+        let id: String = Sha256::digest(&json)[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        // The same result might already be stored, in which case it just gets a new lease of life
+        let path = self.path(&id);
+        if touch(&path).is_ok() {
+            return Ok(id);
+        }
+
+        let tmp = self.dir.join(format!(
+            ".tmp-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, &json)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&tmp);
+                format!("Storing result {} failed: {}", id, err)
+            })?;
+        Ok(id)
+    }
+
+    pub fn load(&self, id: &str) -> Result<Option<StoredResult>, String> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+
+        let path = self.path(id);
+        let json = match fs::read(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("Reading result {} failed: {}", id, err)),
+        };
+        let _ = touch(&path);
+
+        match serde_json::from_slice(&json) {
+            Ok(result) => Ok(Some(result)),
+            Err(err) => Err(format!("Invalid result {}: {}", id, err)),
+        }
+    }
+
+    /// Delete results which are too old, then the oldest results until the rest fit
+    /// within the size limit. Returns how many were deleted.
+    pub fn prune(&self, now: time::SystemTime) -> Result<usize, String> {
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|err| format!("Reading {} failed: {}", self.dir.display(), err))?;
+
+        let mut results = Vec::new();
+        for ent in entries.flatten() {
+            let path = ent.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            if let Ok(meta) = ent.metadata() {
+                let modified = meta.modified().unwrap_or(time::UNIX_EPOCH);
+                results.push((modified, meta.len(), path));
+            }
+        }
+
+        // Newest first, so that the oldest ones are cut off at the end
+        results.sort_by_key(|result| std::cmp::Reverse(result.0));
+        let mut total = 0;
+        let mut deleted = 0;
+        for (modified, size, path) in results {
+            let age = now.duration_since(modified).unwrap_or_default();
+            total += size;
+            if age < self.max_age && total <= self.max_size {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => deleted += 1,
+                Err(err) => warn!(path = %path.display(), error = %err, "Deleting result failed"),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Prune the store every `interval`, in a thread.
+    pub fn start_pruning(self: &Arc<Self>, interval: time::Duration) {
+        let store = self.clone();
+        thread::spawn(move || loop {
+            match store.prune(time::SystemTime::now()) {
+                Ok(0) => (),
+                Ok(deleted) => info!(deleted, "Pruned results"),
+                Err(err) => warn!(error = %err, "Pruning results failed"),
+            }
+            thread::sleep(interval);
+        });
+    }
+}
+
+/// Mark a file as recently used, failing if it doesn't exist.
+fn touch(path: &Path) -> std::io::Result<()> {
+    fs::File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(time::SystemTime::now())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render(result: &StoredResult) -> String {
+    let mut html = String::new();
+    html += "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n";
+    html += "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n";
+    html += &format!("<title>{} result</title>\n", escape(&result.language));
+    html += "<style>\n";
+    html += "body { max-width: 960px; margin: 0 auto; padding: 16px; font-family: system-ui, sans-serif; ";
+    html += "background: #313338; color: #dbdee1; }\n";
+    html += "a { color: #00a8fc; }\nh1 { font-size: 1.3em; }\nh2 { font-size: 0.8em; margin: 16px 0 4px; }\n";
+    html += "pre { background: #2b2d31; border: 1px solid #1e1f22; border-radius: 4px; padding: 8px; ";
+    html += "margin: 0; white-space: pre-wrap; word-break: break-word; }\n";
+    html += "</style>\n</head>\n<body>\n";
+    html += &format!(
+        "<h1>{}: {}</h1>\n",
+        escape(&result.language),
+        escape(&result.exit)
+    );
+    html += &format!("<h2>SOURCE</h2>\n<pre>{}</pre>\n", escape(&result.source));
+    for (name, text) in [("STDOUT", &result.stdout), ("STDERR", &result.stderr)] {
+        if !text.is_empty() {
+            html += &format!("<h2>{}</h2>\n<pre>{}</pre>\n", name, escape(text));
+        }
+    }
+
+    if !result.files.is_empty() {
+        html += "<h2>FILES</h2>\n";
+        for file in &result.files {
+            let filename = file.name.rsplit('/').next().unwrap_or(&file.name);
+            html += &format!(
+                "<a download=\"{}\" href=\"data:application/octet-stream;base64,{}\">{}</a><br>\n",
+                escape(filename),
+                escape(&file.content_base64),
+                escape(&file.name)
+            );
+        }
+    }
+
+    html += "</body>\n</html>\n";
+    html
+}
+
+async fn result_page(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> (StatusCode, Html<String>) {
+    let store = match &state.results {
+        Some(store) => store.clone(),
+        None => return (StatusCode::NOT_FOUND, Html("Not found".to_string())),
+    };
+
+    match tokio::task::spawn_blocking(move || store.load(&id)).await {
+        Ok(Ok(Some(result))) => (StatusCode::OK, Html(render(&result))),
+        Ok(Ok(None)) => {
+            let message = "No such result, it may have expired".to_string();
+            (StatusCode::NOT_FOUND, Html(message))
+        }
+        Ok(Err(err)) => {
+            warn!(error = %err, "Loading result failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, Html("Couldn't load the result".to_string()))
+        }
+        Err(err) => {
+            warn!(error = %err, "Load task failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, Html("Couldn't load the result".to_string()))
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/results/:id", get(result_page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(stdout: &str) -> StoredResult {
+        StoredResult {
+            language: "python".to_string(),
+            source: "print('<b>')".to_string(),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            exit_code: Some(0),
+            signal: None,
+            exit: "Exit Code 0 (OK)".to_string(),
+            files: vec![StoredFile {
+                name: "out/a.txt".to_string(),
+                content_base64: BASE64.encode("hi"),
+            }],
+        }
+    }
+
+    #[test]
+    fn stores_and_prunes_results() {
+        let dir = env::temp_dir().join(format!("langbot-results-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let day = time::Duration::from_secs(24 * 60 * 60);
+        let store = ResultStore::new(dir.clone(), 7 * day, 1024 * 1024, "https://example.com/")
+            .opt_out([42]);
+
+        let id = store.save(&result("<b>")).unwrap();
+        assert!(is_valid_id(&id), "{}", id);
+        assert_eq!(store.save(&result("<b>")).unwrap(), id);
+        let other = store.save(&result("something else")).unwrap();
+        assert_ne!(other, id);
+
+        assert_eq!(store.load(&id).unwrap(), Some(result("<b>")));
+        assert_eq!(store.load("0123456789abcdef0123456789abcdef").unwrap(), None);
+        assert_eq!(store.load("../../etc/passwd").unwrap(), None);
+        assert_eq!(store.link(&id), format!("https://example.com/results/{}", id));
+        assert!(!store.accepts(None) && store.accepts(Some(1)) && !store.accepts(Some(42)));
+
+        let html = render(&result("<b>"));
+        assert!(html.contains("<pre>print('&lt;b&gt;')</pre>"), "{}", html);
+        let download = "download=\"a.txt\" href=\"data:application/octet-stream;base64,aGk=\"";
+        assert!(html.contains(download), "{}", html);
+
+        let now = time::SystemTime::now();
+        assert_eq!(store.prune(now).unwrap(), 0);
+        assert_eq!(store.prune(now + 8 * day).unwrap(), 2);
+        assert_eq!(store.load(&id).unwrap(), None);
+
+        // Only one result fits
+        let size = serde_json::to_vec(&result("a")).unwrap().len() as u64;
+        let small = ResultStore::new(dir.clone(), 7 * day, size + 1, "");
+        small.save(&result("a")).unwrap();
+        small.save(&result("b")).unwrap();
+        assert_eq!(small.prune(now).unwrap(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
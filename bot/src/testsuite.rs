@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::time;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::backend::Backend;
+use crate::manifest::{Conformance, Manifest};
+use crate::podmanager::{ExecResult, Input, Pod, EXEC_TIMEOUT};
+
+/// A test case file, `langs/<lang>/tests/<name>.toml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseFile {
+    source: String,
+    /// The program's stdin
+    #[serde(default)]
+    stdin: String,
+    stdout: Option<String>,
+    stderr: Option<String>,
+    #[serde(default)]
+    exit_code: i32,
+    /// File names in the language's `wd` directory, and regexes for their contents
+    #[serde(default)]
+    files: HashMap<String, String>,
+    /// Only run the case on these machines, like `x86_64`
+    #[serde(default)]
+    machines: Vec<String>,
+}
+
+/// One program to run in a language, and what it should do.
+pub struct Case {
+    pub language: String,
+    pub name: String,
+    source: String,
+    stdin: String,
+    stdout: Option<Regex>,
+    stderr: Option<Regex>,
+    exit_code: i32,
+    files: Vec<(String, Regex)>,
+}
+
+/// The result of running a case.
+pub struct Outcome {
+    pub language: String,
+    pub name: String,
+    pub elapsed: time::Duration,
+    /// Everything that was wrong, empty if the case passed
+    pub problems: Vec<String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+fn machine() -> &'static str {
+    env::consts::ARCH
+}
+
+/// The hello world example names to look for, in the same order as the scripts.
+pub fn hello_world_names() -> [String; 3] {
+    let os = match env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "Darwin",
+        other => other,
+    };
+    [
+        "hello-world.txt".to_string(),
+        format!("hello-world.{}.txt", machine()),
+        format!("hello-world.{}-{}.txt", os, machine()),
+    ]
+}
+
+fn compile(pattern: &str, what: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|err| format!("Invalid {} regex: {}", what, err))
+}
+
+impl Case {
+    /// Parse a test case file. Returns `None` if it's not meant for this machine.
+    pub fn parse(language: &str, name: &str, text: &str) -> Result<Option<Self>, String> {
+        let file: CaseFile = match toml::from_str(text) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Invalid test case: {}", err)),
+        };
+
+        if !file.machines.is_empty() && !file.machines.iter().any(|m| m == machine()) {
+            return Ok(None);
+        }
+
+        let mut files = Vec::new();
+        for (path, pattern) in file.files {
+            let regex = compile(&pattern, &path)?;
+            files.push((path, regex));
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(Some(Self {
+            language: language.to_string(),
+            name: name.to_string(),
+            source: file.source,
+            stdin: file.stdin,
+            stdout: file.stdout.as_deref().map(|re| compile(re, "stdout")).transpose()?,
+            stderr: file.stderr.as_deref().map(|re| compile(re, "stderr")).transpose()?,
+            exit_code: file.exit_code,
+            files,
+        }))
+    }
+
+    /// The case every language gets: its hello world example prints "Hello World".
+    fn hello_world(language: &str, dir: &Path) -> Option<Self> {
+        let source = hello_world_names()
+            .iter()
+            .find_map(|name| fs::read_to_string(dir.join(name)).ok())?;
+        Some(Self {
+            language: language.to_string(),
+            name: "hello-world".to_string(),
+            source,
+            stdin: String::new(),
+            stdout: Some(Regex::new("^Hello World$").unwrap()),
+            stderr: None,
+            exit_code: 0,
+            files: Vec::new(),
+        })
+    }
+
+    /// The cases every language with a `conformance` section in its manifest gets,
+    /// which check that its `run.sh` follows the rules in the README.
+    pub fn conformance(language: &str, snippets: &Conformance) -> Vec<Self> {
+        const UTF8: &str = "Hello, wörld! ✓ 日本語 🦀";
+        const FILE: &str = "conformance.txt";
+
+        let print = |text: &str| snippets.print.replace("{text}", text);
+        let eprint = |text: &str| snippets.eprint.replace("{text}", text);
+        let exit = |code: i32| snippets.exit.replace("{code}", &code.to_string());
+        let write_file = |name: &str, text: &str| {
+            snippets.write_file.replace("{name}", name).replace("{text}", text)
+        };
+        let copy_stdin = snippets.copy_stdin.clone();
+        let case = |name: &str, body: &[String], stdout: Option<&str>, stderr: Option<&str>| Self {
+            language: language.to_string(),
+            name: format!("conformance-{}", name),
+            source: snippets.program.replace("{body}", &body.join("\n")),
+            stdin: String::new(),
+            stdout: stdout.map(|text| Regex::new(&format!("^{}$", regex::escape(text))).unwrap()),
+            stderr: stderr.map(|text| Regex::new(&format!("^{}$", regex::escape(text))).unwrap()),
+            exit_code: 0,
+            files: Vec::new(),
+        };
+
+        vec![
+            Self {
+                exit_code: 7,
+                ..case("exit-code", &[print("before exit"), exit(7)], Some("before exit"), None)
+            },
+            case(
+                "streams",
+                &[print("to stdout"), eprint("to stderr")],
+                Some("to stdout"),
+                Some("to stderr"),
+            ),
+            Self {
+                files: vec![(FILE.to_string(), Regex::new("^file contents\n?$").unwrap())],
+                ..case("files", &[write_file(FILE, "file contents")], None, None)
+            },
+            case("utf8", &[print(UTF8)], Some(UTF8), None),
+            Self {
+                stdin: "line one\nline two\n".to_string(),
+                ..case("stdin", &[copy_stdin], Some("line one\nline two"), None)
+            },
+        ]
+    }
+
+    /// Load the cases of `languages` from `langs_dir`, or of every language if it's empty.
+    pub fn load_all(langs_dir: &Path, languages: &[String]) -> Result<Vec<Self>, String> {
+        let mut names = languages.to_vec();
+        if names.is_empty() {
+            let paths = fs::read_dir(langs_dir)
+                .map_err(|err| format!("Reading {} failed: {}", langs_dir.display(), err))?;
+            names = paths
+                .flatten()
+                .map(|path| path.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+        }
+
+        let mut cases = Vec::new();
+        for language in names {
+            let dir = langs_dir.join(&language);
+            if !dir.is_dir() {
+                return Err(format!("No such language: {}", language));
+            }
+            cases.extend(Self::hello_world(&language, &dir));
+            if let Some(snippets) = Manifest::load(&dir)?.conformance {
+                cases.extend(Self::conformance(&language, &snippets));
+            }
+
+            let mut paths: Vec<_> = match fs::read_dir(dir.join("tests")) {
+                Ok(paths) => paths.flatten().map(|ent| ent.path()).collect(),
+                Err(_) => continue,
+            };
+            paths.sort();
+            for path in paths {
+                if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                    continue;
+                }
+
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                let text = fs::read_to_string(&path)
+                    .map_err(|err| format!("Reading {} failed: {}", path.display(), err))?;
+                let case = Self::parse(&language, &name, &text)
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+                cases.extend(case);
+            }
+        }
+
+        Ok(cases)
+    }
+
+    /// Check the result of running the case, and return what's wrong with it.
+    pub fn check(&self, result: &ExecResult) -> Vec<String> {
+        let mut problems = Vec::new();
+        match result.status.code() {
+            Some(code) if code == self.exit_code => (),
+            Some(code) => problems.push(format!("Exit code {}, expected {}", code, self.exit_code)),
+            None => problems.push("Killed by a signal".to_string()),
+        }
+
+        let outputs = [
+            ("stdout", &self.stdout, &result.stdout),
+            ("stderr", &self.stderr, &result.stderr),
+        ];
+        for (what, regex, output) in outputs {
+            if let Some(regex) = regex {
+                if !regex.is_match(output.as_deref().unwrap_or("")) {
+                    problems.push(format!("{} doesn't match /{}/", what, regex));
+                }
+            }
+        }
+
+        if self.files.is_empty() {
+            return problems;
+        }
+
+        let files = match read_files(result) {
+            Ok(files) => files,
+            Err(err) => {
+                problems.push(err);
+                return problems;
+            }
+        };
+        for (path, regex) in &self.files {
+            match files.get(path) {
+                Some(content) if regex.is_match(content) => (),
+                Some(_) => problems.push(format!("{} doesn't match /{}/", path, regex)),
+                None => problems.push(format!("No file {}", path)),
+            }
+        }
+
+        problems
+    }
+
+    /// Run the case in a new container from the image `tag`.
+    pub fn run(&self, backend: Arc<dyn Backend>, tag: &str) -> Outcome {
+        let start = time::Instant::now();
+        let input = Input {
+            stdin: self.stdin.clone(),
+            args: Vec::new(),
+        };
+        let res = Pod::create(backend, tag).and_then(|mut pod| {
+            pod.execute_with_timeout(&self.language, &self.source, &input, EXEC_TIMEOUT)
+        });
+        let (problems, stdout, stderr) = match res {
+            Ok(result) => (
+                self.check(&result),
+                result.stdout.unwrap_or_default(),
+                result.stderr.unwrap_or_default(),
+            ),
+            Err(err) => (vec![err], String::new(), String::new()),
+        };
+
+        Outcome {
+            language: self.language.clone(),
+            name: self.name.clone(),
+            elapsed: start.elapsed(),
+            problems,
+            stdout,
+            stderr,
+        }
+    }
+}
+
+/// Read the files a program left behind, by their path relative to `wd`.
+fn read_files(result: &ExecResult) -> Result<HashMap<String, String>, String> {
+    let mut files = HashMap::new();
+    let mut archive = match &result.files {
+        Some(archive) => archive.lock().unwrap(),
+        None => return Ok(files),
+    };
+
+    let entries = archive.entries().map_err(|err| format!("Reading files failed: {}", err))?;
+    for ent in entries {
+        let mut ent = ent.map_err(|err| format!("Reading files failed: {}", err))?;
+        if !ent.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = match ent.path() {
+            Ok(path) => path.to_string_lossy().trim_start_matches("./").to_string(),
+            Err(err) => return Err(format!("Invalid file name: {}", err)),
+        };
+        let mut content = Vec::new();
+        ent.read_to_end(&mut content)
+            .map_err(|err| format!("Reading {} failed: {}", path, err))?;
+        files.insert(path, String::from_utf8_lossy(&content).to_string());
+    }
+
+    Ok(files)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            // Not allowed in XML 1.0 at all
+            ch if ch < ' ' && !matches!(ch, '\t' | '\n' | '\r') => escaped += "\u{FFFD}",
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Format outcomes as JUnit XML, with one test suite per language.
+pub fn junit(outcomes: &[Outcome]) -> String {
+    let mut languages: Vec<&str> = outcomes.iter().map(|o| o.language.as_str()).collect();
+    languages.sort();
+    languages.dedup();
+
+    let failures = outcomes.iter().filter(|o| !o.passed()).count();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"langbot\" tests=\"{}\" failures=\"{}\">\n",
+        outcomes.len(),
+        failures
+    );
+    for language in languages {
+        let suite: Vec<&Outcome> = outcomes.iter().filter(|o| o.language == language).collect();
+        let elapsed: time::Duration = suite.iter().map(|o| o.elapsed).sum();
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            escape_xml(language),
+            suite.len(),
+            suite.iter().filter(|o| !o.passed()).count(),
+            elapsed.as_secs_f64()
+        );
+
+        for outcome in suite {
+            xml += &format!(
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">\n",
+                escape_xml(&outcome.language),
+                escape_xml(&outcome.name),
+                outcome.elapsed.as_secs_f64()
+            );
+            if !outcome.passed() {
+                xml += &format!(
+                    "      <failure message=\"{}\">{}</failure>\n",
+                    escape_xml(&outcome.problems[0]),
+                    escape_xml(&outcome.problems.join("\n"))
+                );
+            }
+            if !outcome.stdout.is_empty() {
+                xml += &format!("      <system-out>{}</system-out>\n", escape_xml(&outcome.stdout));
+            }
+            if !outcome.stderr.is_empty() {
+                xml += &format!("      <system-err>{}</system-err>\n", escape_xml(&outcome.stderr));
+            }
+            xml += "    </testcase>\n";
+        }
+
+        xml += "  </testsuite>\n";
+    }
+
+    xml += "</testsuites>\n";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    fn result(code: i32, stdout: &str) -> ExecResult {
+        ExecResult {
+            stdout: Some(stdout.to_string()).filter(|out| !out.is_empty()),
+            stderr: None,
+            status: ExitStatus::from_raw(code << 8),
+            files: None,
+            timed_out: false,
+            cached: false,
+        }
+    }
+
+    #[test]
+    fn checks_results() {
+        let case = Case::parse(
+            "c",
+            "exit",
+            r#"
+            source = "int main() { return 3; }"
+            stdout = "^$"
+            exit_code = 3
+            "#,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert!(case.check(&result(3, "")).is_empty());
+        assert_eq!(case.stdin, "");
+        assert_eq!(
+            case.check(&result(0, "hi")),
+            vec!["Exit code 0, expected 3", "stdout doesn't match /^$/"]
+        );
+
+        let case = Case::parse("c", "files", "source = \"\"\n[files]\n\"out.txt\" = \"x\"\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(case.check(&result(0, "")), vec!["No file out.txt"]);
+
+        let case = Case::parse("c", "stdin", "source = \"\"\nstdin = \"1 2\\n\"\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(case.stdin, "1 2\n");
+
+        let other = if machine() == "x86_64" { "aarch64" } else { "x86_64" };
+        let text = format!("source = \"\"\nmachines = [\"{}\"]\n", other);
+        assert!(Case::parse("asm", "other", &text).unwrap().is_none());
+        assert!(Case::parse("c", "typo", "sourec = \"\"").is_err());
+    }
+
+    #[test]
+    fn generates_conformance_cases() {
+        let manifest = Manifest::parse(
+            r#"
+            [conformance]
+            program = "int main() {\n{body}\n}"
+            print = 'printf("{text}\n");'
+            eprint = 'fprintf(stderr, "{text}\n");'
+            exit = "return {code};"
+            write_file = 'fputs("{text}", fopen("{name}", "w"));'
+            copy_stdin = "for (int c; (c = getchar()) != EOF;) putchar(c);"
+            "#,
+        )
+        .unwrap();
+        let cases = Case::conformance("c", manifest.conformance.as_ref().unwrap());
+
+        let names: Vec<&str> = cases.iter().map(|case| case.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "conformance-exit-code",
+                "conformance-streams",
+                "conformance-files",
+                "conformance-utf8",
+                "conformance-stdin",
+            ]
+        );
+        assert_eq!(
+            cases[0].source,
+            "int main() {\nprintf(\"before exit\\n\");\nreturn 7;\n}"
+        );
+        assert!(cases[0].check(&result(7, "before exit")).is_empty());
+        assert_eq!(cases[2].check(&result(0, "")), vec!["No file conformance.txt"]);
+        assert_eq!(cases[4].stdin, "line one\nline two\n");
+
+        assert!(Manifest::parse("[conformance]\nprint = ''\n").is_err());
+    }
+
+    #[test]
+    fn formats_junit() {
+        let outcome = |name: &str, problems: Vec<String>| Outcome {
+            language: "c".to_string(),
+            name: name.to_string(),
+            elapsed: time::Duration::from_millis(1500),
+            problems,
+            stdout: String::new(),
+            stderr: "a < b".to_string(),
+        };
+        let xml = junit(&[
+            outcome("hello-world", Vec::new()),
+            outcome("exit", vec!["Exit code 0, expected 3".to_string()]),
+        ]);
+
+        assert!(xml.contains("<testsuites name=\"langbot\" tests=\"2\" failures=\"1\">"));
+        assert!(xml.contains("<testsuite name=\"c\" tests=\"2\" failures=\"1\" time=\"3.000\">"));
+        assert!(xml.contains("<failure message=\"Exit code 0, expected 3\">"));
+        assert!(xml.contains("<system-err>a &lt; b</system-err>"));
+    }
+}
@@ -0,0 +1,4 @@
+# Replace gcc with the command which compiles @LANG@ programs
+cat >input.@LANG@
+gcc -x c -o input input.@LANG@
+cd wd && exec ../input "$@" <../stdin
@@ -0,0 +1,3 @@
+# Replace osyris with whatever compile.sh installed in $DEPLOYDIR
+cat >input.@LANG@
+cd wd && exec ../osyris ../input.@LANG@ "$@" <../stdin
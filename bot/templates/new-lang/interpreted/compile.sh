@@ -0,0 +1,5 @@
+# The interpreter comes from the base image, so there's nothing to build here.
+# If it isn't in there, install it with apt-get in the Containerfile,
+# or build it from source like the git-source template does.
+
+touch "$DEPLOYDIR/.done"
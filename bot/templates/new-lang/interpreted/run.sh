@@ -0,0 +1,3 @@
+# Replace python3 with the command which runs @LANG@ programs
+cat >input.@LANG@
+cd wd && exec python3 ../input.@LANG@ "$@" <../stdin
@@ -0,0 +1,400 @@
+//! A simulated Discord for driving the handler without a network connection,
+//! with a fake backend which runs tiny scripts instead of real languages.
+
+// Every test binary uses a different part of this
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use langbot::backend::{Backend, ExecOutput};
+use langbot::cache::ResultCache;
+use langbot::discord::{Discord, Handler, Reply};
+use langbot::executor::Executor;
+use langbot::languages::Registry;
+use langbot::podmanager::PodManager;
+use langbot::routes::Router;
+use langbot::store::ResultStore;
+use serde_json::json;
+use serenity::async_trait;
+use serenity::model::channel::Message;
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::user::CurrentUser;
+
+pub const GUILD: GuildId = GuildId(5);
+pub const CHANNEL: ChannelId = ChannelId(10);
+pub const BOT: UserId = UserId(20);
+pub const USER: UserId = UserId(30);
+
+/// Runs every program as a script of lines like:
+///
+/// ```text
+/// out hello       print "hello" to stdout
+/// err oops        print "oops" to stderr
+/// cat             print the program's stdin
+/// args            print the program's arguments, separated by spaces
+/// file a.txt hi   create the file a.txt containing "hi"
+/// exit 3          exit with code 3
+/// crash           fail the execution, like a container which disappeared
+/// ```
+#[derive(Default)]
+pub struct FakeBackend {
+    next_id: AtomicU64,
+    /// The files each container created in its last run
+    files: Mutex<HashMap<String, Vec<(String, String)>>>,
+    /// Every language and program which ran, in order
+    pub runs: Mutex<Vec<(String, String)>>,
+    /// The image each container was created from
+    tags: Mutex<HashMap<String, String>>,
+    /// Images in which every language fails its self-test
+    pub broken: Mutex<HashSet<String>>,
+    /// Every container which was reset, in order
+    pub resets: Mutex<Vec<String>>,
+    /// Every container which was killed, in order
+    pub kills: Mutex<Vec<String>>,
+    /// Image IDs which aren't just "sha256:<tag>", like those of rebuilt images
+    pub image_ids: Mutex<HashMap<String, String>>,
+}
+
+impl FakeBackend {
+    /// The image the container `id` was created from.
+    pub fn tag(&self, id: &str) -> Option<String> {
+        self.tags.lock().unwrap().get(id).cloned()
+    }
+
+    /// How many containers were created
+    pub fn created(&self) -> usize {
+        self.tags.lock().unwrap().len()
+    }
+}
+
+impl Backend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn create(&self, tag: &str, _labels: &[(&str, &str)]) -> Result<String, String> {
+        let id = format!("fake-{}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        self.tags.lock().unwrap().insert(id.clone(), tag.to_string());
+        Ok(id)
+    }
+
+    fn image_id(&self, tag: &str) -> Result<String, String> {
+        match self.image_ids.lock().unwrap().get(tag) {
+            Some(id) => Ok(id.clone()),
+            None => Ok(format!("sha256:{}", tag)),
+        }
+    }
+
+    /// Runs the program the way `scripts/run.sh` splits up its arguments and stdin.
+    fn run(
+        &self,
+        id: &str,
+        language: &str,
+        args: &[String],
+        stdin: &[u8],
+    ) -> Result<ExecOutput, String> {
+        let (input_size, args) = match args.split_first() {
+            Some((size, args)) => (size.parse().unwrap(), args),
+            None => (0, args),
+        };
+        let (input, program) = stdin.split_at(input_size);
+        let program = String::from_utf8_lossy(program).to_string();
+        self.runs
+            .lock()
+            .unwrap()
+            .push((language.to_string(), program.clone()));
+
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut files = Vec::new();
+        let mut code = 0;
+        for line in program.lines() {
+            let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match command {
+                "out" => stdout += &format!("{}\n", rest),
+                "cat" => stdout += &String::from_utf8_lossy(input),
+                "args" => stdout += &format!("{}\n", args.join(" ")),
+                "err" => stderr += &format!("{}\n", rest),
+                "file" => {
+                    let (name, content) = rest.split_once(' ').unwrap_or((rest, ""));
+                    files.push((name.to_string(), content.to_string()));
+                }
+                "exit" => code = rest.parse().unwrap(),
+                "crash" => return Err("The container went away".into()),
+                _ => (),
+            }
+        }
+
+        self.files.lock().unwrap().insert(id.to_string(), files);
+        Ok(ExecOutput {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+
+    fn files(&self, id: &str, _language: &str) -> Result<Vec<u8>, String> {
+        let files = self.files.lock().unwrap().remove(id).unwrap_or_default();
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        Ok(builder.into_inner().unwrap())
+    }
+
+    fn prime(&self, _id: &str, _language: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Every language passes, unless the image is broken. "Every language" is just python.
+    fn self_test(&self, id: &str, languages: &[String]) -> Result<String, String> {
+        let broken = match self.tag(id) {
+            Some(tag) => self.broken.lock().unwrap().contains(&tag),
+            None => return Err(format!("No such container: {}", id)),
+        };
+        let languages = match languages.is_empty() {
+            true => vec!["python".to_string()],
+            false => languages.to_vec(),
+        };
+        Ok(languages
+            .iter()
+            .map(|language| match broken {
+                true => format!("fail {} wrong output\n", language),
+                false => format!("ok {}\n", language),
+            })
+            .collect())
+    }
+
+    fn snapshot(&self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn reset(&self, id: &str) -> Result<(), String> {
+        self.resets.lock().unwrap().push(id.to_string());
+        Ok(())
+    }
+
+    fn kill(&self, id: &str) -> Result<(), String> {
+        self.kills.lock().unwrap().push(id.to_string());
+        Ok(())
+    }
+
+    fn list(&self, _key: &str) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    fn remove(&self, _ids: &[String]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Something the handler did on Discord.
+#[derive(Debug, PartialEq)]
+pub enum Sent {
+    Say { channel: ChannelId, text: String },
+    Reply { to: MessageId, id: MessageId, reply: Reply },
+    Edit { id: MessageId, reply: Reply },
+}
+
+/// Records what the handler does instead of sending it anywhere.
+#[derive(Default)]
+struct Recorder {
+    next_id: AtomicU64,
+    sent: Mutex<Vec<Sent>>,
+    /// Make every call fail with this error
+    failure: Mutex<Option<String>>,
+}
+
+impl Recorder {
+    fn check(&self) -> Result<(), String> {
+        match self.failure.lock().unwrap().clone() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Discord for Recorder {
+    async fn say(&self, channel: ChannelId, text: String) -> Result<(), String> {
+        self.check()?;
+        self.sent.lock().unwrap().push(Sent::Say { channel, text });
+        Ok(())
+    }
+
+    async fn reply(
+        &self,
+        to: (ChannelId, MessageId),
+        reply: Reply,
+    ) -> Result<(ChannelId, MessageId), String> {
+        self.check()?;
+        let id = MessageId(1_000_000 + self.next_id.fetch_add(1, Ordering::SeqCst));
+        self.sent.lock().unwrap().push(Sent::Reply {
+            to: to.1,
+            id,
+            reply,
+        });
+        Ok((to.0, id))
+    }
+
+    async fn edit(&self, message: (ChannelId, MessageId), reply: Reply) -> Result<(), String> {
+        self.check()?;
+        self.sent.lock().unwrap().push(Sent::Edit {
+            id: message.1,
+            reply,
+        });
+        Ok(())
+    }
+}
+
+/// A bot connected to a simulated Discord with one guild with one channel,
+/// in which the user `USER` talks to the bot `BOT`.
+pub struct Simulator {
+    pub backend: Arc<FakeBackend>,
+    pub languages: Arc<Registry>,
+    handler: Handler,
+    discord: Recorder,
+    next_id: AtomicU64,
+}
+
+fn user(id: UserId, name: &str, bot: bool) -> serde_json::Value {
+    json!({
+        "id": id.0.to_string(),
+        "username": name,
+        "discriminator": "0001",
+        "avatar": null,
+        "bot": bot,
+    })
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::build(None, None)
+    }
+
+    /// A bot which saves results to `results`.
+    pub fn with_results(results: Arc<ResultStore>) -> Self {
+        Self::build(Some(results), None)
+    }
+
+    /// A bot which reuses results from `cache`.
+    pub fn with_cache(cache: ResultCache) -> Self {
+        Self::build(None, Some(cache))
+    }
+
+    fn build(results: Option<Arc<ResultStore>>, cache: Option<ResultCache>) -> Self {
+        let backend = Arc::new(FakeBackend::default());
+        let podman = PodManager::standalone(backend.clone(), Router::single("langbot"));
+        let languages = podman.languages();
+        let mut executor = Executor::new(Arc::new(podman));
+        if let Some(cache) = cache {
+            executor = executor.cache(cache);
+        }
+        let executor = Arc::new(executor);
+        let connected = Arc::new(AtomicBool::new(true));
+        let handler = Handler::new(executor, languages.clone(), connected, results);
+
+        let mut me = CurrentUser::default();
+        me.id = BOT;
+        me.name = "langbot".to_string();
+        me.bot = true;
+        handler.set_user(me);
+
+        Self {
+            backend,
+            languages,
+            handler,
+            discord: Recorder::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Make every call to Discord fail with `err` from now on.
+    pub fn fail_with(&self, err: &str) {
+        *self.discord.failure.lock().unwrap() = Some(err.to_string());
+    }
+
+    async fn deliver(
+        &self,
+        content: &str,
+        author: serde_json::Value,
+        mentions: Vec<serde_json::Value>,
+    ) -> MessageId {
+        let id = MessageId(self.next_id.fetch_add(1, Ordering::SeqCst));
+        let msg: Message = serde_json::from_value(json!({
+            "id": id.0.to_string(),
+            "attachments": [],
+            "author": author,
+            "channel_id": CHANNEL.0.to_string(),
+            "content": content,
+            "edited_timestamp": null,
+            "embeds": [],
+            "guild_id": GUILD.0.to_string(),
+            "type": 0,
+            "mention_everyone": false,
+            "mention_roles": [],
+            "mentions": mentions,
+            "pinned": false,
+            "timestamp": "2022-05-01T12:00:00Z",
+            "tts": false,
+        }))
+        .unwrap();
+
+        self.handler.handle_message(&self.discord, msg).await;
+        id
+    }
+
+    /// `USER` sends `content`.
+    pub async fn send(&self, content: &str) -> MessageId {
+        self.deliver(content, user(USER, "user", false), Vec::new()).await
+    }
+
+    /// `USER` sends `content`, mentioning the bot.
+    pub async fn mention(&self, content: &str) -> MessageId {
+        let mentions = vec![user(BOT, "langbot", true)];
+        self.deliver(content, user(USER, "user", false), mentions).await
+    }
+
+    /// Another bot sends `content`.
+    pub async fn send_as_bot(&self, content: &str) -> MessageId {
+        self.deliver(content, user(UserId(40), "otherbot", true), Vec::new()).await
+    }
+
+    /// `USER` edits their message `id` into `content`.
+    pub async fn edit(&self, id: MessageId, content: &str) {
+        let evt: MessageUpdateEvent = serde_json::from_value(json!({
+            "id": id.0.to_string(),
+            "channel_id": CHANNEL.0.to_string(),
+            "guild_id": GUILD.0.to_string(),
+            "content": content,
+            "author": user(USER, "user", false),
+        }))
+        .unwrap();
+
+        self.handler.handle_message_update(&self.discord, evt).await;
+    }
+
+    /// Everything the bot did since the last call.
+    pub fn take(&self) -> Vec<Sent> {
+        std::mem::take(&mut *self.discord.sent.lock().unwrap())
+    }
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{Sent, Simulator, CHANNEL, GUILD};
+use langbot::cache::ResultCache;
+use langbot::discord::{Attachment, Embed, Reply};
+use langbot::selftest::Report;
+use langbot::store::ResultStore;
+use serenity::utils::Color;
+
+fn field(name: &str, text: &str) -> (String, String) {
+    (name.to_string(), format!("```ansi\n{}\n```", text))
+}
+
+#[tokio::test]
+async fn replies_with_output() {
+    let sim = Simulator::new();
+    let id = sim.send("!python `out hello`").await;
+
+    let sent = sim.take();
+    let reply = match &sent[..] {
+        [Sent::Reply { to, reply, .. }] if *to == id => reply,
+        _ => panic!("Expected one reply, got {:?}", sent),
+    };
+    assert_eq!(
+        reply,
+        &Reply {
+            content: None,
+            embed: Some(Embed {
+                description: "Exit Code 0 (OK)".to_string(),
+                color: Color::DARK_GREEN,
+                fields: vec![field("STDOUT", "hello")],
+                link: None,
+                footer: None,
+            }),
+            attachments: Vec::new(),
+        }
+    );
+    assert_eq!(
+        sim.backend.runs.lock().unwrap().clone(),
+        vec![("python".to_string(), "out hello".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn runs_code_blocks() {
+    let sim = Simulator::new();
+    sim.send("Look at this:\n!C\n```c\nerr oh no\nexit 139\n```").await;
+
+    let sent = sim.take();
+    let embed = match &sent[..] {
+        [Sent::Reply { reply: Reply { embed: Some(embed), .. }, .. }] => embed,
+        _ => panic!("Expected one reply with an embed, got {:?}", sent),
+    };
+    assert_eq!(embed.description, "Exit Code 139 (SIGSEGV)");
+    assert_eq!(embed.color, Color::DARK_RED);
+    assert_eq!(embed.fields, vec![field("STDERR", "oh no")]);
+    assert_eq!(
+        sim.backend.runs.lock().unwrap()[0],
+        ("c".to_string(), "err oh no\nexit 139\n".to_string())
+    );
+}
+
+#[tokio::test]
+async fn escapes_and_truncates_output() {
+    let sim = Simulator::new();
+    let program: String = (0..30).map(|i| format!("out ``{}``\n", i)).collect();
+    sim.send(&format!("!sh\n```\n{}```", program)).await;
+
+    let sent = sim.take();
+    let embed = match &sent[..] {
+        [Sent::Reply { reply: Reply { embed: Some(embed), .. }, .. }] => embed,
+        _ => panic!("Expected one reply with an embed, got {:?}", sent),
+    };
+    let (_, stdout) = &embed.fields[0];
+    let last = "`\u{200B}`\u{200B}19`\u{200B}`\u{200B} (truncated...)";
+    assert!(stdout.contains(last), "{}", stdout);
+    assert!(!stdout.contains("20"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn attaches_files() {
+    let sim = Simulator::new();
+    sim.send("!python `file out.txt hi`").await;
+    sim.send("!python\n```\nfile out.txt hi\nout done\n```").await;
+
+    let sent = sim.take();
+    let file = Attachment {
+        filename: "out.txt".to_string(),
+        data: b"hi".to_vec(),
+    };
+    match &sent[..] {
+        [Sent::Reply { reply: quiet, .. }, Sent::Reply { reply: chatty, .. }] => {
+            // Only files, so there's no need for an embed
+            assert_eq!(quiet.embed, None);
+            assert_eq!(quiet.attachments, vec![file.clone()]);
+            assert!(chatty.embed.is_some());
+            assert_eq!(chatty.attachments, vec![file]);
+        }
+        _ => panic!("Expected two replies, got {:?}", sent),
+    }
+}
+
+#[tokio::test]
+async fn edits_reply_when_message_is_edited() {
+    let sim = Simulator::new();
+    let id = sim.send("!python `out one`").await;
+    let reply_id = match &sim.take()[..] {
+        [Sent::Reply { id, .. }] => *id,
+        sent => panic!("Expected one reply, got {:?}", sent),
+    };
+
+    sim.edit(id, "!python `out two`").await;
+    match &sim.take()[..] {
+        [Sent::Edit { id, reply }] => {
+            assert_eq!(*id, reply_id);
+            assert_eq!(reply.embed.as_ref().unwrap().fields, vec![field("STDOUT", "two")]);
+        }
+        sent => panic!("Expected one edit, got {:?}", sent),
+    }
+
+    // Errors replace the reply's content, and remove the old output
+    sim.edit(id, "!python `crash`").await;
+    match &sim.take()[..] {
+        [Sent::Edit { id, reply }] => {
+            assert_eq!(*id, reply_id);
+            assert_eq!(reply.content.as_deref(), Some("Error: The container went away"));
+            assert_eq!(reply.embed, None);
+        }
+        sent => panic!("Expected one edit, got {:?}", sent),
+    }
+
+    // Edits which don't contain code are left alone
+    sim.edit(id, "never mind").await;
+    assert_eq!(sim.take(), Vec::new());
+}
+
+#[tokio::test]
+async fn ignores_edits_of_other_messages() {
+    let sim = Simulator::new();
+    let id = sim.send("hello").await;
+    sim.edit(id, "!python `out hi`").await;
+    assert_eq!(sim.take(), Vec::new());
+    assert!(sim.backend.runs.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn ignores_bots_and_chatter() {
+    let sim = Simulator::new();
+    sim.send_as_bot("!python `out hi`").await;
+    sim.send("just talking about !python").await;
+    assert_eq!(sim.take(), Vec::new());
+    assert!(sim.backend.runs.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn explains_itself_when_mentioned() {
+    let sim = Simulator::new();
+    sim.mention("what do you do?").await;
+
+    match &sim.take()[..] {
+        [Sent::Say { channel, text }] => {
+            assert_eq!(*channel, CHANNEL);
+            assert!(text.starts_with("I'm langbot!"), "{}", text);
+            assert!(text.contains("`python`"), "{}", text);
+        }
+        sent => panic!("Expected one message, got {:?}", sent),
+    }
+}
+
+#[tokio::test]
+async fn reports_errors() {
+    let sim = Simulator::new();
+    sim.send("!python `crash`").await;
+    assert_eq!(
+        sim.take(),
+        vec![Sent::Say {
+            channel: CHANNEL,
+            text: "Error: The container went away".to_string(),
+        }]
+    );
+
+    // When Discord is broken too, there's nothing to record
+    sim.fail_with("Discord is down");
+    sim.send("!python `out hi`").await;
+    assert_eq!(sim.take(), Vec::new());
+}
+
+#[tokio::test]
+async fn links_to_stored_results() {
+    let dir = std::env::temp_dir().join(format!("langbot-sim-results-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let store = ResultStore::new(dir.clone(), day, 1024 * 1024, "https://example.com");
+
+    let sim = Simulator::with_results(Arc::new(store));
+    sim.send("!python `out hello`").await;
+    let link = match &sim.take()[..] {
+        [Sent::Reply { reply: Reply { embed: Some(embed), .. }, .. }] => {
+            embed.link.clone().unwrap()
+        }
+        sent => panic!("Expected one reply with an embed, got {:?}", sent),
+    };
+    let id = link.strip_prefix("https://example.com/results/").unwrap();
+    let store = ResultStore::new(dir.clone(), day, 1024 * 1024, "https://example.com");
+    let result = store.load(id).unwrap().unwrap();
+    assert_eq!(result.source, "out hello");
+    assert_eq!(result.stdout, "hello");
+
+    // Guilds which opted out get no link, and nothing is stored
+    let store = ResultStore::new(dir.clone(), day, 1024 * 1024, "https://example.com")
+        .opt_out([GUILD.0]);
+    let sim = Simulator::with_results(Arc::new(store));
+    sim.send("!python `out secret`").await;
+    match &sim.take()[..] {
+        [Sent::Reply { reply: Reply { embed: Some(embed), .. }, .. }] => {
+            assert_eq!(embed.link, None)
+        }
+        sent => panic!("Expected one reply with an embed, got {:?}", sent),
+    }
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn reuses_deterministic_results() {
+    let cache = ResultCache::new(["c".to_string()], Duration::from_secs(60), 1024 * 1024);
+    let sim = Simulator::with_cache(cache);
+    let id = sim.send("!c `out one`").await;
+    sim.edit(id, "!c `out two`").await;
+    sim.edit(id, "!c `out one`").await;
+    sim.send("!python `out one`").await;
+    sim.send("!python `out one`").await;
+
+    let footers: Vec<Option<String>> = sim
+        .take()
+        .into_iter()
+        .map(|sent| match sent {
+            Sent::Reply { reply, .. } | Sent::Edit { reply, .. } => reply.embed.unwrap().footer,
+            sent => panic!("Expected a reply or an edit, got {:?}", sent),
+        })
+        .collect();
+    let cached = Some("Cached result".to_string());
+    assert_eq!(footers, vec![None, None, cached, None, None]);
+
+    // Python isn't deterministic, so it runs every time
+    let runs: Vec<String> = sim
+        .backend
+        .runs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, code)| code.clone())
+        .collect();
+    assert_eq!(runs, vec!["out one", "out two", "out one", "out one"]);
+}
+
+#[tokio::test]
+async fn disabled_languages_get_no_cached_results() {
+    let cache = ResultCache::new(["c".to_string()], Duration::from_secs(60), 1024 * 1024);
+    let sim = Simulator::with_cache(cache);
+    sim.send("!c `out one`").await;
+    let report = Report {
+        passed: Vec::new(),
+        failed: vec![("c".to_string(), "no compiler".to_string())],
+        skipped: Vec::new(),
+    };
+    sim.languages.record(&report, |_| true);
+    sim.send("!c `out one`").await;
+
+    let sent = sim.take();
+    match &sent[..] {
+        [Sent::Reply { .. }, Sent::Say { text, .. }] => {
+            assert_eq!(text, "Error: c is disabled, it failed its self-test: no compiler");
+        }
+        sent => panic!("Expected a reply and an error, got {:?}", sent),
+    }
+    assert_eq!(sim.backend.runs.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::FakeBackend;
+use langbot::podmanager::{Input, PodManager, EXEC_TIMEOUT};
+use langbot::routes::Router;
+
+fn pod_manager() -> (Arc<FakeBackend>, PodManager) {
+    // Every test in this binary reuses pods, so it doesn't matter which one sets this first
+    env::set_var("LANGBOT_POD_REUSE", "2");
+    let backend = Arc::new(FakeBackend::default());
+    let podman = PodManager::standalone(backend.clone(), Router::single("langbot"));
+    (backend, podman)
+}
+
+/// Wait up to a few seconds for `cond` to hold.
+fn wait_for(mut cond: impl FnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if cond() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn failed_switch_keeps_old_image() {
+    let (backend, podman) = pod_manager();
+    backend.broken.lock().unwrap().insert("langbot-new".to_string());
+
+    let err = podman.switch_image("langbot", "langbot-new").err().unwrap();
+    assert!(err.starts_with("langbot-new: "), "{}", err);
+    assert_eq!(podman.image_id("python").as_deref(), Some("sha256:langbot"));
+
+    let mut pod = podman.get_pod("python").unwrap();
+    assert_eq!(backend.tag(pod.id()).as_deref(), Some("langbot"));
+    let output = pod.execute("python", "out still here").unwrap();
+    assert_eq!(output.stdout.as_deref(), Some("still here"));
+    drop(pod);
+
+    // Once it's fixed, the switch goes through
+    backend.broken.lock().unwrap().clear();
+    podman.switch_image("langbot", "langbot-new").unwrap();
+    assert_eq!(podman.image_id("python").as_deref(), Some("sha256:langbot-new"));
+    let pod = podman.get_pod("python").unwrap();
+    assert_eq!(backend.tag(pod.id()).as_deref(), Some("langbot-new"));
+}
+
+#[test]
+fn starts_containers_when_asked() {
+    let (backend, podman) = pod_manager();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(backend.created(), 0);
+
+    let pod = podman.get_pod("python").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(backend.created(), 1);
+    assert_eq!(backend.tag(pod.id()).as_deref(), Some("langbot"));
+}
+
+#[test]
+fn discards_pods_which_failed() {
+    let (backend, podman) = pod_manager();
+
+    let mut pod = podman.get_pod("python").unwrap();
+    let id = pod.id().to_string();
+    pod.execute("python", "out hi").unwrap();
+    podman.recycle(pod);
+    assert!(wait_for(|| backend.resets.lock().unwrap().contains(&id)));
+    assert!(!backend.kills.lock().unwrap().contains(&id));
+
+    let mut pod = podman.get_pod("python").unwrap();
+    let id = pod.id().to_string();
+    let resets = backend.resets.lock().unwrap().len();
+    assert!(pod.execute("python", "crash").is_err());
+    podman.recycle(pod);
+
+    // A reusable pod would be reset in the background, this one is killed right away
+    assert!(backend.kills.lock().unwrap().contains(&id));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(backend.resets.lock().unwrap().len(), resets);
+}
+
+#[test]
+fn passes_on_stdin_and_arguments() {
+    let (_, podman) = pod_manager();
+
+    let mut pod = podman.get_pod("python").unwrap();
+    let input = Input {
+        stdin: "cat\nline\n".to_string(),
+        args: vec!["-v".to_string(), "two words".to_string()],
+    };
+    let output = pod.execute_with_timeout("python", "args\ncat", &input, EXEC_TIMEOUT).unwrap();
+    // The program's stdin isn't mistaken for more of its source
+    assert_eq!(output.stdout.as_deref(), Some("-v two words\ncat\nline"));
+}
+
+#[test]
+fn switches_to_rebuilt_images() {
+    let (backend, podman) = pod_manager();
+    let router = Router::single("langbot");
+
+    // Nothing changed, so there's nothing to switch to
+    podman.switch_routes(router.clone(), &[]).unwrap();
+    assert_eq!(podman.image_id("python").as_deref(), Some("sha256:langbot"));
+
+    // The same tag with a new ID is a new build, which has to pass its self-test
+    backend.image_ids.lock().unwrap().insert("langbot".to_string(), "sha256:v2".to_string());
+    backend.broken.lock().unwrap().insert("langbot".to_string());
+    assert!(podman.switch_routes(router.clone(), &[]).is_err());
+    assert_eq!(podman.image_id("python").as_deref(), Some("sha256:langbot"));
+
+    backend.broken.lock().unwrap().clear();
+    podman.switch_routes(router, &[]).unwrap();
+    assert_eq!(podman.image_id("python").as_deref(), Some("sha256:v2"));
+}
+
+#[test]
+fn tests_languages_moved_to_running_images() {
+    env::set_var("LANGBOT_POD_REUSE", "2");
+    let backend = Arc::new(FakeBackend::default());
+    let router = Router::parse("[images]\nlangbot-ml = [\"ocaml\"]\n").unwrap();
+    let podman = PodManager::standalone(backend.clone(), router);
+
+    // ocaml would move to langbot, which is already running, but doesn't work there
+    backend.broken.lock().unwrap().insert("langbot".to_string());
+    let err = podman.switch_routes(Router::single("langbot"), &[]).err().unwrap();
+    assert!(err.starts_with("langbot: "), "{}", err);
+    let pod = podman.get_pod("ocaml").unwrap();
+    assert_eq!(backend.tag(pod.id()).as_deref(), Some("langbot-ml"));
+    drop(pod);
+
+    backend.broken.lock().unwrap().clear();
+    podman.switch_routes(Router::single("langbot"), &[]).unwrap();
+    let pod = podman.get_pod("ocaml").unwrap();
+    assert_eq!(backend.tag(pod.id()).as_deref(), Some("langbot"));
+}
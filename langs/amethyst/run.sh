@@ -1,5 +1,5 @@
 cat >input.amy
-./amethyst input.amy &> /dev/null && cd wd && exec ../a.out
+./amethyst input.amy &> /dev/null && cd wd && exec ../a.out "$@" <../stdin
 ./amethystc input.amy
 exit 1
 
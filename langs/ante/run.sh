@@ -1,3 +1,3 @@
 cat >input.ante
 export XDG_CONFIG_HOME="$PWD/.config"
-cd wd && exec ../ante --run ../input.ante
+cd wd && exec ../ante --run ../input.ante "$@" <../stdin
@@ -1,3 +1,3 @@
 cat >input.s
 gcc -static -o input input.s
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -1,3 +1,3 @@
 echo '#lang barrel' >input.rkt
 cat >>input.rkt
-cd wd && racket ../input.rkt
+cd wd && racket ../input.rkt "$@" <../stdin
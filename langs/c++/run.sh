@@ -1,3 +1,3 @@
 cat > input.cc
 g++ -std=c++2a -o input input.cc
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -1,3 +1,3 @@
 cat > input.c
 gcc -std=c18 -o input input.c
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
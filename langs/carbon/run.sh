@@ -1,2 +1,2 @@
 cat >input.cb
-cd wd && exec ../carbon ../input.cb
+cd wd && exec ../carbon ../input.cb "$@" <../stdin
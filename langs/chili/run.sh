@@ -1,3 +1,3 @@
 cat >input.chili
 ./chili build input.chili
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
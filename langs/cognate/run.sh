@@ -1,3 +1,3 @@
 cat >input.cog
 ./bin/cognac input.cog
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -1,5 +1,5 @@
 cat >input.gw
-cd wd && ../bin/gwion -p../.gwplug -dSndfile ../input.gw 2>../stderr.log
+cd wd && ../bin/gwion -p../.gwplug -dSndfile ../input.gw <../stdin 2>../stderr.log
 if [ -s ../stderr.log ]; then
 	cat ../stderr.log >&2
 	rm -f gwion.wav
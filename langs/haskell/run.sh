@@ -1,3 +1,3 @@
 cat >input.hs
 ghc -o input input.hs >/dev/null
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -1,3 +1,3 @@
 export HOOK_HOME="$PWD/hook-lang"
 cat >input.hk
-cd wd && exec ../hook ../input.hk
+cd wd && exec ../hook ../input.hk "$@" <../stdin
@@ -1,2 +1,2 @@
 cat >input.lua
-cd wd && exec luajit ../input.lua
+cd wd && exec luajit ../input.lua "$@" <../stdin
@@ -1,3 +1,3 @@
 cat >input.ml
 ocamlc -o input input.ml
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -17,4 +17,4 @@ fi
 
 # Execute the programme:
 cd ..
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -1,2 +1,2 @@
 cat > input.pl
-cd wd && swipl -q -f ../input.pl -t main
+cd wd && swipl -q -f ../input.pl -t main -- "$@" <../stdin
@@ -1,2 +1,2 @@
 cat >input.prw
-cd wd && exec ../prowl.exe -i ../input.prw
+cd wd && exec ../prowl.exe -i ../input.prw "$@" <../stdin
@@ -4,7 +4,7 @@
 # to avoid uploading it.
 cd wd
 cat >input.rpl
-if node ../rpl input.rpl; then
+if node ../rpl input.rpl "$@" <../stdin; then
 	ret=0
 else
 	ret=$?
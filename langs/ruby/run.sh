@@ -1,2 +1,2 @@
 cat >input.rb
-cd wd && exec ruby ../input.rb
+cd wd && exec ruby ../input.rb "$@" <../stdin
@@ -1,3 +1,3 @@
 cat >input.rs
 rustc -o input input.rs
-cd wd && exec ../input
+cd wd && exec ../input "$@" <../stdin
@@ -0,0 +1,27 @@
+#!/bin/bash
+set -euo pipefail
+
+lang="$1"
+
+if [ -z "$lang" ]; then
+	echo "Usage: $0 <language>"
+	exit 1
+fi
+
+topdir="$PWD"
+deploydir="$topdir/deploy/$lang"
+
+if ! [ -f "$deploydir/.done" ]; then
+	echo "Language $lang isn't deployed" >&2
+	exit 1
+fi
+
+# Languages can get their runtime going before the container is checkpointed,
+# anything prewarm.sh leaves running is restored along with the container.
+# Without one, a checkpoint is just an idle container, which is no faster than a new one.
+cd "$deploydir"
+if ! [ -f prewarm.sh ]; then
+	echo "Language $lang has no prewarm.sh, so there's nothing to checkpoint" >&2
+	exit 1
+fi
+exec bash -euo pipefail prewarm.sh
@@ -0,0 +1,19 @@
+#!/bin/bash
+set -euo pipefail
+
+lang="$1"
+
+if [ -z "$lang" ]; then
+	echo "Usage: $0 <language>"
+	exit 1
+fi
+
+# Run the language's hello world example, to get everything it needs into the page cache.
+# This picks the example the same way run-tests.sh does.
+machine="$(uname -m)"
+os="$(uname -s)"
+for f in "hello-world.txt" "hello-world.$machine.txt" "hello-world.$os-$machine.txt"; do
+	if [ -f "langs/$lang/$f" ]; then
+		exec ./scripts/run.sh "$lang" <"langs/$lang/$f" >/dev/null
+	fi
+done
@@ -0,0 +1,128 @@
+#!/bin/bash
+# Reset a container after an execution so that it can be reused:
+# kill every process, and bring the parts of the file system which the runner
+# can write to back to what they were when the container was started.
+# Exits with a non-zero status if the container can't be made clean,
+# in which case it must be thrown away.
+#
+# The Containerfile installs this as /usr/local/bin/langbot-reset if the image is built
+# with REUSE=1, and records
+# the directories the runner can write to with 'langbot-reset roots'.
+# The bot runs 'langbot-reset snapshot' when a container is started and
+# 'langbot-reset' after every execution, both as root, so that the code we run
+# can't tamper with the snapshot.
+set -euo pipefail
+export LC_ALL=C
+
+statedir=/etc/langbot
+
+# Print the directories the runner can write to whose parent it can't write to.
+# This walks the whole file system, so it's only run once, when the image is built.
+# The Containerfile makes /app read-only for the runner other than the top of each
+# deploy/<lang> directory, where it can only add files, and its wd, so the roots end up
+# being those, /tmp, the runner's home and whatever else the base image leaves writable.
+roots() {
+	find / -xdev \( -path /proc -o -path /sys \) -prune -o -type d -writable -print 2>/dev/null |
+		while read -r dir; do
+			if ! [ -w "$(dirname "$dir")" ]; then
+				echo "$dir"
+			fi
+		done
+}
+
+# List everything under the roots, NUL separated and sorted.
+# The ctime catches any change to a file, and can't be faked.
+# Directories don't record it, since it changes whenever something is created in them.
+# Directories the runner can't write to are listed but not walked, since nothing in them
+# can change (a writable directory further down is a root of its own), which skips
+# the toolchains in deploy/<lang>.
+list() {
+	while read -r root; do
+		find "$root" -xdev \
+			\( -type d ! -user runner ! -perm -0002 ! \( -group runner -perm -0020 \) \
+			-printf 'd %m %U %G - %p\0' -prune \) -o \
+			\( -type d -printf 'd %m %U %G - %p\0' \) -o \
+			-printf '%y %m %U %G %C@ %p\0'
+	done <"$statedir/roots" | sort -z
+}
+
+# Set 'leftovers' to the processes which are still around, other than PID 1 and ourselves.
+# Only uses builtins, so that it doesn't see its own children.
+find_leftovers() {
+	leftovers=()
+	local dir pid stat
+	for dir in /proc/[0-9]*; do
+		pid="${dir#/proc/}"
+		if [ "$pid" = 1 ] || [ "$pid" = "$$" ]; then
+			continue
+		fi
+
+		if read -r stat <"$dir/stat" 2>/dev/null; then
+			leftovers+=("$stat")
+		fi
+	done
+}
+
+kill_all() {
+	# Signals everything except PID 1 and ourselves
+	kill -KILL -1 2>/dev/null || true
+
+	local i
+	for i in $(seq 20); do
+		find_leftovers
+		if [ "${#leftovers[@]}" = 0 ]; then
+			return 0
+		fi
+		sleep 0.05
+	done
+
+	# Zombies end up here too, since nothing reaps orphans
+	echo "Leftover processes:" >&2
+	printf '  %s\n' "${leftovers[@]}" >&2
+	return 1
+}
+
+restore_files() {
+	list >"$statedir/current"
+
+	# Anything which was removed or modified can't be restored
+	local changed
+	changed="$(comm -z -23 "$statedir/manifest" "$statedir/current" | tr '\0' '\n')"
+	if [ -n "$changed" ]; then
+		echo "Files from the image were changed:" >&2
+		echo "$changed" | head -n 20 >&2
+		return 1
+	fi
+
+	# So everything else is new, and can just be removed
+	local line
+	while IFS= read -r -d '' line; do
+		rm -rf -- "${line#* * * * * }"
+	done < <(comm -z -13 "$statedir/manifest" "$statedir/current")
+
+	# /dev/shm is a separate mount, so it isn't under any of the roots
+	if [ -d /dev/shm ]; then
+		find /dev/shm -mindepth 1 -delete
+	fi
+}
+
+case "${1:-}" in
+roots)
+	roots
+	;;
+snapshot)
+	list >"$statedir/manifest"
+	;;
+'')
+	if ! [ -f "$statedir/manifest" ]; then
+		echo "No snapshot, run '$0 snapshot' first" >&2
+		exit 1
+	fi
+	kill_all
+	restore_files
+	;;
+*)
+	echo "Usage: $0 [roots|snapshot]" >&2
+	exit 1
+	;;
+esac
@@ -1,12 +1,16 @@
 #!/bin/bash
 set -euo pipefail
 
-lang="$1"
+# Stdin is the program's input, <stdin size> bytes of it, followed by its source.
+# The arguments after that are the program's.
+lang="${1:-}"
+stdin_size="${2:-0}"
 
 if [ -z "$lang" ]; then
-	echo "Usage: $0 <language>"
+	echo "Usage: $0 <language> [<stdin size> [<arg>...]]"
 	exit 1
 fi
+shift $(($# < 2 ? $# : 2))
 
 topdir="$PWD"
 deploydir="$topdir/deploy/$lang"
@@ -17,6 +21,9 @@ if ! [ -f "$deploydir/.done" ]; then
 fi
 
 cd "$deploydir"
-rm -rf wd
+# wd may be a mount point, so empty it rather than removing it
 mkdir -p wd
-exec bash -euo pipefail run.sh
+find wd -mindepth 1 -delete
+# The language's run.sh gets the source on stdin, and gives the program this file instead
+head -c "$stdin_size" >stdin
+exec bash -euo pipefail run.sh "$@"
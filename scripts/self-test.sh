@@ -0,0 +1,45 @@
+#!/bin/bash
+# Run the hello world example of the given languages, or of every deployed language,
+# and check that it prints 'Hello World'. For each language, prints one of:
+#   ok <lang>
+#   fail <lang> <reason>
+#   skip <lang>           (there's no example for this machine)
+set -uo pipefail
+
+machine="$(uname -m)"
+os="$(uname -s)"
+
+if [ $# -gt 0 ]; then
+	langs="$*"
+else
+	langs="$(ls deploy)"
+fi
+
+for lang in $langs; do
+	if ! [ -f "deploy/$lang/.done" ]; then
+		echo "fail $lang not deployed"
+		continue
+	fi
+
+	f=""
+	for name in "hello-world.txt" "hello-world.$machine.txt" "hello-world.$os-$machine.txt"; do
+		if [ -f "langs/$lang/$name" ]; then
+			f="langs/$lang/$name"
+			break
+		fi
+	done
+	if [ -z "$f" ]; then
+		echo "skip $lang"
+		continue
+	fi
+
+	output="$(timeout 30 ./scripts/run.sh "$lang" <"$f" 2>/dev/null)"
+	status=$?
+	if [ "$status" != 0 ]; then
+		echo "fail $lang exit code $status"
+	elif [ "$output" != "Hello World" ]; then
+		echo "fail $lang wrong output"
+	else
+		echo "ok $lang"
+	fi
+done